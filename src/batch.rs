@@ -0,0 +1,433 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::io::Write;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::bbsort::*;
+use crate::vagraph::cost::Objective;
+use crate::vagraph::encoding::EncodingCost;
+use crate::vagraph::vag::*;
+
+// the number of bins of the histograms: the improvements are binned by 10%, the kendall
+// taus by 0.2 (from -1 to +1)
+const HISTOGRAM_BINS: usize = 10;
+
+/// The parameters of the evaluation of a corpus of graphs.
+///
+/// # Fields
+///
+/// * `layout`  - the layout strategy the graphs are sorted with;
+/// * `unit`    - the unit of the lengths of the jumps in the costs;
+/// * `threads` - the number of threads the graphs are sorted on (0: as many as the cores);
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchConfig {
+    pub layout: Layout,
+    pub unit: SizeUnit,
+    pub threads: usize,
+}
+
+/// The comparison of the sorted order of a graph to its original order (see CfgOrder).
+///
+/// # Fields
+///
+/// * `cost`                - the cost of the sorted order;
+/// * `original_cost`       - the cost of the original order;
+/// * `encoding`            - the code size and taken branches of the sorted order;
+/// * `original_encoding`   - the code size and taken branches of the original order;
+/// * `kendall_tau`         - the difference of the two orders (see CfgOrder);
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OrderStats {
+    pub cost: usize,
+    pub original_cost: usize,
+    pub encoding: EncodingCost,
+    pub original_encoding: EncodingCost,
+    pub kendall_tau: f64,
+}
+
+impl OrderStats {
+    // whether the sorted order is at least as good as the original one
+    pub fn is_better(&self) -> bool {
+        self.cost <= self.original_cost
+    }
+
+    // the cost of the sorted order relative to the original one - if that is not zero
+    pub fn cost_ratio(&self) -> Option<f64> {
+        (self.original_cost != 0).then(|| self.cost as f64 / self.original_cost as f64)
+    }
+}
+
+/// The evaluation of a single graph of a corpus.
+///
+/// # Fields
+///
+/// * `entry`   - the address of the entry block of the graph;
+/// * `nodes`   - the number of blocks of the graph;
+/// * `stats`   - the comparison of the orders if the graph could be sorted;
+/// * `error`   - the reason why the graph could not be evaluated otherwise;
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphEvaluation {
+    pub entry: u64,
+    pub nodes: usize,
+    pub stats: Option<OrderStats>,
+    pub error: Option<EvaluationError>,
+}
+
+/// The errors of the evaluation of a single graph of a corpus.
+///
+/// # Variants
+///
+/// * `Sort`    - the graph could not be sorted (see SortError);
+/// * `Cost`    - the sorted order could not be compared to the original one (see CostError);
+///
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvaluationError {
+    Sort(SortError),
+    Cost(CostError),
+}
+
+impl Display for EvaluationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sort(err) => write!(f, "{err}"),
+            Self::Cost(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for EvaluationError {}
+
+/// The aggregate statistics of the evaluations of a corpus.
+///
+/// # Fields
+///
+/// * `graphs`                  - the number of graphs;
+/// * `failed`                  - the number of graphs which could not be sorted;
+/// * `better`                  - the number of sorted orders cheaper than the original ones;
+/// * `unchanged`               - the number of sorted orders as cheap as the original ones;
+/// * `worse`                   - the number of sorted orders more expensive than the original ones;
+/// * `improvement_histogram`   - the better graphs binned by their improvement (by 10%);
+/// * `mean_cost_ratio`         - the mean of the sorted costs over the original ones;
+/// * `median_cost_ratio`       - the median of the sorted costs over the original ones;
+/// * `kendall_tau_histogram`   - the sorted graphs binned by their kendall tau (by 0.2);
+/// * `mean_kendall_tau`        - the mean of the kendall taus;
+/// * `median_kendall_tau`      - the median of the kendall taus;
+/// * `failures`                - the number of failed graphs by their errors;
+///
+/// Note that the ratios are only taken over the graphs whose original cost is not zero.
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CorpusStats {
+    pub graphs: usize,
+    pub failed: usize,
+    pub better: usize,
+    pub unchanged: usize,
+    pub worse: usize,
+    pub improvement_histogram: [usize; HISTOGRAM_BINS],
+    pub mean_cost_ratio: Option<f64>,
+    pub median_cost_ratio: Option<f64>,
+    pub kendall_tau_histogram: [usize; HISTOGRAM_BINS],
+    pub mean_kendall_tau: Option<f64>,
+    pub median_kendall_tau: Option<f64>,
+    pub failures: BTreeMap<String, usize>,
+}
+
+// the bin of a value in [low, high] among HISTOGRAM_BINS equal bins (the last one is closed)
+fn bin(value: f64, low: f64, high: f64) -> usize {
+    let bin = ((value - low) / (high - low) * HISTOGRAM_BINS as f64).floor();
+    (bin.max(0.0) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+// note: the values must be sorted
+fn median(values: &[f64]) -> Option<f64> {
+    let n = values.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(values[n / 2]),
+        _ => Some((values[n / 2 - 1] + values[n / 2]) / 2.0),
+    }
+}
+
+impl CorpusStats {
+    // the aggregate statistics of the given evaluations
+    pub fn of(evaluations: &[GraphEvaluation]) -> Self {
+        let mut stats = CorpusStats {
+            graphs: evaluations.len(),
+            ..Default::default()
+        };
+        let mut ratios: Vec<f64> = Vec::new();
+        let mut taus: Vec<f64> = Vec::new();
+
+        for evaluation in evaluations {
+            if let Some(error) = &evaluation.error {
+                stats.failed += 1;
+                *stats.failures.entry(format!("{error:?}")).or_default() += 1;
+            }
+            let Some(order) = &evaluation.stats else {
+                continue;
+            };
+
+            match order.cost.cmp(&order.original_cost) {
+                std::cmp::Ordering::Less => {
+                    stats.better += 1;
+                    let improvement = 1.0 - order.cost as f64 / order.original_cost as f64;
+                    stats.improvement_histogram[bin(improvement, 0.0, 1.0)] += 1;
+                }
+                std::cmp::Ordering::Equal => stats.unchanged += 1,
+                std::cmp::Ordering::Greater => stats.worse += 1,
+            }
+            ratios.extend(order.cost_ratio());
+            // note: the kendall tau of a single block is not defined
+            if order.kendall_tau.is_finite() {
+                taus.push(order.kendall_tau);
+                stats.kendall_tau_histogram[bin(order.kendall_tau, -1.0, 1.0)] += 1;
+            }
+        }
+
+        ratios.sort_by(f64::total_cmp);
+        taus.sort_by(f64::total_cmp);
+        stats.mean_cost_ratio = mean(&ratios);
+        stats.median_cost_ratio = median(&ratios);
+        stats.mean_kendall_tau = mean(&taus);
+        stats.median_kendall_tau = median(&taus);
+
+        stats
+    }
+}
+
+impl Display for CorpusStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let optional = |x: Option<f64>| x.map_or(String::from("-"), |x| format!("{x:.3}"));
+
+        writeln!(f, "graphs: {}, failed: {}", self.graphs, self.failed)?;
+        writeln!(
+            f,
+            "better: {}, unchanged: {}, worse: {}",
+            self.better, self.unchanged, self.worse
+        )?;
+        writeln!(
+            f,
+            "improvement histogram (by 10%): {:?}",
+            self.improvement_histogram
+        )?;
+        writeln!(
+            f,
+            "cost ratio: mean {}, median {}",
+            optional(self.mean_cost_ratio),
+            optional(self.median_cost_ratio)
+        )?;
+        writeln!(
+            f,
+            "kendall tau histogram (by 0.2): {:?}",
+            self.kendall_tau_histogram
+        )?;
+        writeln!(
+            f,
+            "kendall tau: mean {}, median {}",
+            optional(self.mean_kendall_tau),
+            optional(self.median_kendall_tau)
+        )?;
+        for (error, count) in &self.failures {
+            writeln!(f, "failures ({error}): {count}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The evaluations of the graphs of a corpus (in the order of the corpus) together with
+/// their aggregate statistics.
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CorpusReport {
+    pub evaluations: Vec<GraphEvaluation>,
+    pub stats: CorpusStats,
+}
+
+impl CorpusReport {
+    // writes the report as JSON
+    pub fn write_json<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(&mut *output, self)?;
+        writeln!(output)
+    }
+
+    // writes the evaluations as CSV, one graph per line
+    pub fn write_csv<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        writeln!(
+            output,
+            "entry,nodes,cost,original_cost,cost_ratio,kendall_tau,code_size,original_code_size,\
+             taken_branches,original_taken_branches,error"
+        )?;
+        for evaluation in &self.evaluations {
+            write!(output, "{:#x},{},", evaluation.entry, evaluation.nodes)?;
+            match &evaluation.stats {
+                Some(stats) => write!(
+                    output,
+                    "{},{},{},{},{},{},{},{},",
+                    stats.cost,
+                    stats.original_cost,
+                    stats.cost_ratio().map_or(String::new(), |x| x.to_string()),
+                    stats.kendall_tau,
+                    stats.encoding.code_size,
+                    stats.original_encoding.code_size,
+                    stats.encoding.taken_branches,
+                    stats.original_encoding.taken_branches
+                )?,
+                None => write!(output, ",,,,,,,,")?,
+            }
+            match &evaluation.error {
+                Some(error) => writeln!(output, "{error:?}")?,
+                None => writeln!(output)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// sorts the graph and compares the sorted order to the original one
+fn evaluate(graph: &UnwrappedVAGraph<u64>, config: &BatchConfig) -> GraphEvaluation {
+    let vag = graph.to_vag();
+    let entry = vag.address();
+    let mut evaluation = GraphEvaluation {
+        entry: entry.id().unwrap_or_default(),
+        nodes: vag.nodes().len(),
+        stats: None,
+        error: None,
+    };
+
+    let order = match cfg_sort_with(&vag, entry, config.layout) {
+        Ok(order) => order,
+        Err(error) => {
+            evaluation.error = Some(EvaluationError::Sort(error));
+            return evaluation;
+        }
+    };
+    match cfg_cost_with(&vag, entry, &order, &Objective::Distance(config.unit)) {
+        Ok(cost) => {
+            evaluation.stats = Some(OrderStats {
+                cost: cost.cost(),
+                original_cost: cost.original_cost(),
+                encoding: cost.encoding(),
+                original_encoding: cost.original_encoding(),
+                kendall_tau: cost.kendall_tau(),
+            })
+        }
+        Err(error) => evaluation.error = Some(EvaluationError::Cost(error)),
+    }
+
+    evaluation
+}
+
+/// Sorts every graph of a corpus and compares the sorted orders to the original ones (see
+/// cfg_cost), in parallel on the given number of threads. The evaluations are returned in
+/// the order of the corpus, together with their aggregate statistics.
+///
+/// # Arguments
+///
+/// * `graphs`  - the graphs of the corpus (e.g. read from a YAML file);
+/// * `config`  - the layout, the unit of the costs and the number of threads;
+///
+/// The graphs which can not be evaluated are recorded with their errors (see EvaluationError).
+///
+pub fn evaluate_corpus(graphs: &[UnwrappedVAGraph<u64>], config: &BatchConfig) -> CorpusReport {
+    let evaluations: Vec<GraphEvaluation> = with_threads(config.threads, || {
+        graphs
+            .par_iter()
+            .map(|graph| evaluate(graph, config))
+            .collect()
+    });
+
+    CorpusReport {
+        stats: CorpusStats::of(&evaluations),
+        evaluations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two sortable graphs (a diamond and a loop) and one whose entry is missing
+    const CORPUS: &str = "
+- address: 0x0
+  nodes:
+  - { address: 0x0, len: 1, targets: [0x1, 0x2], indegree: 0 }
+  - { address: 0x1, len: 2, targets: [0x3], indegree: 1 }
+  - { address: 0x2, len: 3, targets: [0x3], indegree: 1 }
+  - { address: 0x3, len: 1, targets: [], indegree: 2 }
+- address: 0x10
+  nodes:
+  - { address: 0x10, len: 2, targets: [0x12], indegree: 0 }
+  - { address: 0x11, len: 1, targets: [], indegree: 1 }
+  - { address: 0x12, len: 4, targets: [0x12, 0x11], indegree: 2 }
+- address: 0x20
+  nodes:
+  - { address: 0x21, len: 1, targets: [], indegree: 0 }
+";
+
+    #[test]
+    fn corpus_evaluation() {
+        let graphs: Vec<UnwrappedVAGraph<u64>> = serde_yaml::from_str(CORPUS).unwrap();
+        let config = BatchConfig {
+            threads: 2,
+            ..Default::default()
+        };
+        let report = evaluate_corpus(&graphs, &config);
+
+        // the evaluations are in the order of the corpus
+        let entries: Vec<u64> = report.evaluations.iter().map(|x| x.entry).collect();
+        assert_eq!(entries, [0x0, 0x10, 0x20]);
+        assert_eq!(
+            report.evaluations[2].error,
+            Some(EvaluationError::Sort(SortError::InvalidInitialAddress))
+        );
+
+        let stats = &report.stats;
+        assert_eq!((stats.graphs, stats.failed), (3, 1));
+        assert_eq!(stats.better + stats.unchanged + stats.worse, 2);
+        assert_eq!(
+            stats.failures,
+            BTreeMap::from([(String::from("Sort(InvalidInitialAddress)"), 1)])
+        );
+        assert_eq!(stats.kendall_tau_histogram.iter().sum::<usize>(), 2);
+
+        // the same on a single thread
+        let single = evaluate_corpus(
+            &graphs,
+            &BatchConfig {
+                threads: 1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(single, report);
+
+        let mut csv: Vec<u8> = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert_eq!(
+            csv.lines().last(),
+            Some("0x20,1,,,,,,,,,Sort(InvalidInitialAddress)")
+        );
+    }
+
+    #[test]
+    fn medians_and_bins() {
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[1.0, 2.0, 4.0]), Some(2.0));
+        assert_eq!(median(&[1.0, 2.0, 4.0, 8.0]), Some(3.0));
+        assert_eq!(bin(1.0, 0.0, 1.0), HISTOGRAM_BINS - 1);
+        assert_eq!(bin(-1.0, -1.0, 1.0), 0);
+        assert_eq!(bin(0.05, 0.0, 1.0), 0);
+    }
+}
@@ -1,13 +1,20 @@
 // pub mod vagraph;
+use crate::vagraph::anneal::AnnealConfig;
+use crate::vagraph::cost::Objective;
+use crate::vagraph::encoding::EncodingCost;
+use crate::vagraph::exact::OPTIMAL_MAX_NODES;
+use crate::vagraph::split::HotColdSplit;
+use crate::vagraph::tiebreak::{FollowSuccessor, TieBreak};
 use crate::vagraph::vag::*;
 
 // generic functions
 
 use kendalls::tau_b;
 use petgraph::visit::{GraphBase, IntoNeighbors, IntoNeighborsDirected, IntoNodeIdentifiers};
+use rayon::prelude::*;
+use serde::Serialize;
 use std::cmp::*;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::default::Default;
 use std::error::Error;
 use std::fmt::{Debug, Display, LowerHex};
@@ -30,13 +37,13 @@ where
         return Err(SortError::InvalidInitialAddress);
     }
 
-    let mut nodes: HashMap<Vertex<G::NodeId>, NoInstrBasicBlock<G::NodeId>> = HashMap::new();
+    let mut nodes: BTreeMap<Vertex<G::NodeId>, NoInstrBasicBlock<G::NodeId>> = BTreeMap::new();
     let valid_nodes: HashSet<G::NodeId> = g.node_identifiers().collect();
 
     // going over all the vertices of the given input graph
     // we collect the relevant data in a hashmap, which will be used later in the VAGraph instance
     for block in g.node_identifiers() {
-        let sources: std::collections::HashSet<Vertex<G::NodeId>> = g
+        let sources: std::collections::BTreeSet<Vertex<G::NodeId>> = g
             .neighbors_directed(block, petgraph::Direction::Incoming)
             .filter_map(|id| valid_nodes.contains(&id).then_some(Vertex::Id(id)))
             .collect();
@@ -45,21 +52,42 @@ where
         // since hashset deletes the multiple edges -> we only need its size
         let indegree: usize = sources.len();
 
-        let targets: std::collections::HashSet<Vertex<G::NodeId>> = g
+        let targets: std::collections::BTreeSet<Vertex<G::NodeId>> = g
             .neighbors_directed(block, petgraph::Direction::Outgoing)
             .filter_map(|id| valid_nodes.contains(&id).then_some(Vertex::Id(id)))
             .collect();
 
-        nodes.insert(
+        let mut node = NoInstrBasicBlock::<G::NodeId>::new(
             Vertex::Id(block),
-            NoInstrBasicBlock::<G::NodeId>::new(
-                Vertex::Id(block),
-                g.weight(block),
-                sources,
-                targets,
-                indegree,
-            ),
+            g.weight(block),
+            sources,
+            targets,
+            indegree,
         );
+        if let Some(bytes) = g.byte_size(block) {
+            node = node.with_bytes(bytes);
+        }
+        if let Some(branch) = g.branch_size(block) {
+            node = node.with_branch(branch);
+        }
+        if let Some(fallthrough) = g.fallthrough(block).filter(|x| valid_nodes.contains(x)) {
+            node = node.with_fallthrough(Vertex::Id(fallthrough));
+        }
+        if let Some(jump) = g.jump(block).filter(|x| valid_nodes.contains(x)) {
+            node = node.with_jump(Vertex::Id(jump));
+        }
+        if let Some(count) = g.execution_count(block) {
+            node = node.with_count(count);
+        }
+        for target in g.neighbors_directed(block, petgraph::Direction::Outgoing) {
+            if let (true, Some(weight)) =
+                (valid_nodes.contains(&target), g.edge_weight(block, target))
+            {
+                node = node.with_edge_weight(Vertex::Id(target), weight);
+            }
+        }
+
+        nodes.insert(Vertex::Id(block), node);
     }
 
     let vag: VirtualAddressGraph<G::NodeId> = VirtualAddressGraph::new(Vertex::Id(entry), nodes);
@@ -75,26 +103,9 @@ where
     Ok(vag)
 }
 
-/// Returns an order on the blocks of the given control flow graph, such that
-/// the overall jumps' weights are locally minimalized.
-/// This local minimalization is achieved by Kahn's algorithm spiced up with the
-/// following tiebraking heuristic: whenever in a step of the Kahn's algorithm
-/// there are multiple nodes to put in the order we choose that which has the most
-/// incoming edges (originally) AND the most number of instructions.
-///
-/// # Arguments
-///
-/// * `g` - the control flow graph (satisfying several natural traits from petgraph);
-/// * `entry` - the starting blocks address (which hence must be a node of g);
-///
-/// # Errors
-///
-/// See below!
-///
-/// etc.
-///
-
-pub fn cfg_sort<G>(g: G, entry: G::NodeId) -> Result<Vec<G::NodeId>, SortError>
+// the VAG of the given graph, if it can be sorted: it is not empty, the entry is one of its
+// nodes and every node is reachable from the entry
+fn sortable_vag<G>(g: G, entry: G::NodeId) -> Result<VirtualAddressGraph<G::NodeId>, SortError>
 where
     G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
     <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
@@ -125,11 +136,380 @@ where
         return Err(SortError::UnreachableNodes);
     }
 
-    let topsort = vag.weighted_order();
+    Ok(vag)
+}
+
+/// Returns an order on the blocks of the given control flow graph, such that
+/// the overall jumps' weights are locally minimalized.
+/// This local minimalization is achieved by Kahn's algorithm spiced up with the
+/// following tiebraking heuristic: whenever in a step of the Kahn's algorithm
+/// there are multiple nodes to put in the order we choose that which has the most
+/// incoming edges (originally) AND the most number of instructions.
+///
+/// # Arguments
+///
+/// * `g` - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry` - the starting blocks address (which hence must be a node of g);
+///
+/// # Errors
+///
+/// See below!
+///
+/// etc.
+///
+
+pub fn cfg_sort<G>(g: G, entry: G::NodeId) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    cfg_sort_with(g, entry, Layout::Kahn)
+}
+
+/// The available strategies to order the blocks of a control flow graph.
+///
+/// # Variants
+///
+/// * `Kahn`            - Kahn's algorithm with the tiebreaking heuristic described at cfg_sort;
+/// * `PettisHansen`    - Pettis and Hansen's bottom-up chain formation;
+/// * `Fallthrough`     - Kahn's algorithm following the successors of the last placed block;
+///
+/// The `PettisHansen` layout visits the edges by descending weight and makes the heaviest
+/// ones fall-throughs, then places the chains one after the other starting from the entry's.
+/// The `Fallthrough` layout places a ready successor of the last placed block next whenever
+/// there is one (the hottest one, then the original fall-through), otherwise it falls back
+/// to the heuristic of `Kahn` (see FollowSuccessor).
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    Kahn,
+    PettisHansen,
+    Fallthrough,
+}
+
+/// Returns an order on the blocks of the given control flow graph using the given
+/// layout strategy. Note that the edge weights needed by the `PettisHansen` layout
+/// come from the profile if there is one (see NodeWeight), otherwise they are estimated
+/// statically: edges inside loops are considered to be hotter.
+///
+/// # Arguments
+///
+/// * `g`       - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`   - the starting blocks address (which hence must be a node of g);
+/// * `layout`  - the strategy used to compute the order;
+///
+/// # Errors
+///
+/// The same as for cfg_sort.
+///
+pub fn cfg_sort_with<G>(g: G, entry: G::NodeId, layout: Layout) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    let vag = sortable_vag(g, entry)?;
+
+    let topsort = layout_order(&vag, layout);
     assert_eq!(topsort.len(), g.node_identifiers().count());
     Ok(topsort)
 }
 
+// the order of the blocks of a sortable VAG (see sortable_vag) by the given layout strategy
+fn layout_order<N: VAGNodeId>(vag: &VirtualAddressGraph<N>, layout: Layout) -> Vec<N> {
+    match layout {
+        Layout::Kahn => vag.weighted_order(),
+        Layout::PettisHansen => vag.pettis_hansen_order(),
+        Layout::Fallthrough => vag.weighted_order_by(&FollowSuccessor),
+    }
+}
+
+/// Returns an order on the blocks of the given control flow graph by Kahn's algorithm (as
+/// cfg_sort does), where the ties between the blocks ready to be placed are broken by the
+/// given strategy instead of the default heuristic (see TieBreak and DegreeLength).
+///
+/// # Arguments
+///
+/// * `g`           - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`       - the starting blocks address (which hence must be a node of g);
+/// * `tie_break`   - the strategy choosing the next block (e.g. AddressOrder or a closure);
+///
+/// # Errors
+///
+/// The same as for cfg_sort.
+///
+pub fn cfg_sort_by<G, T>(g: G, entry: G::NodeId, tie_break: &T) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+    T: TieBreak<G::NodeId> + ?Sized,
+{
+    let vag = sortable_vag(g, entry)?;
+    let topsort = vag.weighted_order_by(tie_break);
+    assert_eq!(topsort.len(), g.node_identifiers().count());
+    Ok(topsort)
+}
+
+// runs f on a thread pool of the given size (0: as many threads as the cores)
+// note: if the pool can not be built, f runs on the global pool
+pub(crate) fn with_threads<T, F>(threads: usize, f: F) -> T
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+        Ok(pool) => pool.install(f),
+        Err(err) => {
+            log::warn!("cannot build a thread pool of {threads} threads: {err}");
+            f()
+        }
+    }
+}
+
+/// Sorts many control flow graphs concurrently with the given layout strategy (see
+/// cfg_sort_with) on a thread pool of the given size. The orders (or the errors) are
+/// returned in the order of the graphs, independently of the number of threads.
+///
+/// # Arguments
+///
+/// * `graphs`  - the control flow graphs with their starting blocks' addresses;
+/// * `layout`  - the strategy used to compute the orders;
+/// * `threads` - the number of threads (0: as many as the cores);
+///
+/// # Errors
+///
+/// The same as for cfg_sort, for every graph separately.
+///
+pub fn cfg_sort_many<G>(
+    graphs: &[(G, G::NodeId)],
+    layout: Layout,
+    threads: usize,
+) -> Vec<Result<Vec<G::NodeId>, SortError>>
+where
+    G: IntoNodeIdentifiers
+        + IntoNeighbors
+        + IntoNeighborsDirected
+        + NodeWeight<Node = G::NodeId>
+        + Copy
+        + Sync,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord + Send + Sync,
+{
+    with_threads(threads, || {
+        graphs
+            .par_iter()
+            .map(|&(g, entry)| cfg_sort_with(g, entry, layout))
+            .collect()
+    })
+}
+
+/// Returns an order on the blocks of the given control flow graph using the given
+/// layout strategy, split into a hot and a cold region: the cold blocks were executed
+/// at most threshold times in the profile (see NodeWeight), where a block without an
+/// execution count is executed as many times as its incoming edges are taken. Both
+/// regions keep the relative order of the layout and the entry block is always hot.
+/// Without a profile every block is hot.
+///
+/// # Arguments
+///
+/// * `g`           - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`       - the starting blocks address (which hence must be a node of g);
+/// * `layout`      - the strategy used to compute the order;
+/// * `threshold`   - the maximal execution count of the cold blocks (0 for never executed);
+///
+/// # Errors
+///
+/// The same as for cfg_sort.
+///
+pub fn cfg_sort_split<G>(
+    g: G,
+    entry: G::NodeId,
+    layout: Layout,
+    threshold: usize,
+) -> Result<HotColdSplit<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    let vag = sortable_vag(g, entry)?;
+    let order = layout_order(&vag, layout);
+
+    Ok(vag.split_order(&order, threshold))
+}
+
+/// Returns an order on the blocks of the given control flow graph with minimal cost
+/// (in the sense of cfg_cost) among all the orders starting with the entry block.
+/// The optimum is found by a dynamic programming over the subsets of blocks, hence it
+/// is only feasible for small graphs - but it tells how far the heuristics are from optimal.
+///
+/// # Arguments
+///
+/// * `g`       - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`   - the starting blocks address (which hence must be a node of g);
+///
+/// # Errors
+///
+/// The same as for cfg_sort, and `TooManyNodes` if g has more than OPTIMAL_MAX_NODES nodes.
+///
+pub fn optimal_order<G>(g: G, entry: G::NodeId) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    let vag = to_vag(g, entry)?;
+
+    if !vag.unreachable_from_start().is_empty() {
+        return Err(SortError::UnreachableNodes);
+    }
+    if vag.nodes().len() > OPTIMAL_MAX_NODES {
+        return Err(SortError::TooManyNodes);
+    }
+
+    Ok(vag.optimal_order())
+}
+
+/// Improves a given order on the blocks of a control flow graph by local search: it
+/// repeatedly moves a block, swaps two blocks, reverses a segment or exchanges two
+/// consecutive segments of the order whenever that decreases the cost (in the sense
+/// of cfg_cost). The entry block is placed first and stays there.
+///
+/// # Arguments
+///
+/// * `g`       - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`   - the starting blocks address (which hence must be a node of g);
+/// * `order`   - the initial order, e.g. the output of cfg_sort;
+/// * `budget`  - the maximal number of orders whose cost is evaluated;
+///
+/// # Errors
+///
+/// The same as for cfg_sort, and `InvalidOrder` if order is not an order of the nodes of g.
+///
+pub fn improve_order<G>(
+    g: G,
+    entry: G::NodeId,
+    order: &[G::NodeId],
+    budget: usize,
+) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    improve_order_with(g, entry, order, budget, &Objective::default())
+}
+
+/// The same as improve_order, but the local search decreases the given objective, e.g.
+/// the number of cache lines and pages touched by the hot blocks (see Objective).
+///
+/// # Arguments
+///
+/// * `g`           - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`       - the starting blocks address (which hence must be a node of g);
+/// * `order`       - the initial order, e.g. the output of cfg_sort;
+/// * `budget`      - the maximal number of orders whose cost is evaluated;
+/// * `objective`   - the cost to be decreased;
+///
+/// # Errors
+///
+/// The same as for improve_order.
+///
+pub fn improve_order_with<G>(
+    g: G,
+    entry: G::NodeId,
+    order: &[G::NodeId],
+    budget: usize,
+    objective: &Objective,
+) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    let vag = to_vag(g, entry)?;
+    let initial = initial_order(&vag, entry, order)?;
+
+    Ok(vag.improve_order_with(&initial, budget, objective))
+}
+
+/// Searches for a better order on the blocks of a control flow graph than the given one
+/// by simulated annealing, where the objective is the cost (in the sense of cfg_cost).
+/// The search is reproducible: it is driven by a random number generator with a fixed
+/// seed, and reports its progress through the log crate. The entry block is placed first
+/// and stays there.
+///
+/// # Arguments
+///
+/// * `g`       - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`   - the starting blocks address (which hence must be a node of g);
+/// * `order`   - the initial order, e.g. the output of cfg_sort;
+/// * `config`  - the seed, the iteration and time budget and the temperatures of the search;
+///
+/// # Errors
+///
+/// The same as for improve_order.
+///
+pub fn anneal_order<G>(
+    g: G,
+    entry: G::NodeId,
+    order: &[G::NodeId],
+    config: &AnnealConfig,
+) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    anneal_order_with(g, entry, order, config, &Objective::default())
+}
+
+/// The same as anneal_order, but the search decreases the given objective (see Objective).
+///
+/// # Arguments
+///
+/// * `g`           - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`       - the starting blocks address (which hence must be a node of g);
+/// * `order`       - the initial order, e.g. the output of cfg_sort;
+/// * `config`      - the seed, the iteration and time budget and the temperatures of the search;
+/// * `objective`   - the cost to be decreased;
+///
+/// # Errors
+///
+/// The same as for improve_order.
+///
+pub fn anneal_order_with<G>(
+    g: G,
+    entry: G::NodeId,
+    order: &[G::NodeId],
+    config: &AnnealConfig,
+    objective: &Objective,
+) -> Result<Vec<G::NodeId>, SortError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord,
+{
+    let vag = to_vag(g, entry)?;
+    let initial = initial_order(&vag, entry, order)?;
+
+    Ok(vag.anneal_order_with(&initial, config, objective))
+}
+
+// checks that the given order is an order of the nodes of the graph and puts the entry first
+fn initial_order<N: VAGNodeId>(
+    vag: &VirtualAddressGraph<N>,
+    entry: N,
+    order: &[N],
+) -> Result<Vec<N>, SortError> {
+    let nodes: BTreeSet<Vertex<N>> = order.iter().map(|&x| Vertex::Id(x)).collect();
+    if nodes.len() != order.len() || nodes.len() != vag.nodes().len() {
+        return Err(SortError::InvalidOrder);
+    }
+    if nodes.iter().any(|x| !vag.nodes().contains_key(x)) {
+        return Err(SortError::InvalidOrder);
+    }
+
+    // the entry block goes first
+    let mut initial: Vec<N> = vec![entry];
+    initial.extend(order.iter().filter(|&&x| x != entry));
+
+    Ok(initial)
+}
+
 /// Given an order on the block of a control flow graph, it returns an instance of the CfgOrder
 /// struct (for definition see below) to gain information about the performance of that order
 /// compared to the original order of the blocks (by default: ascending by the addresses)
@@ -156,8 +536,63 @@ where
     G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
     <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord + Default,
 {
-    // TODO: what if the input is bad again ?
-    let vag: VirtualAddressGraph<G::NodeId> = to_vag(g, entry).unwrap();
+    cfg_cost_in(g, entry, order, SizeUnit::Instructions)
+}
+
+/// The same as cfg_cost, but the lengths of the jumps are measured in the given unit:
+/// either in instructions (as cfg_cost does) or in bytes. Note that whenever the size of
+/// a block in bytes is not known (see NodeWeight), its number of instructions is used.
+///
+/// # Arguments
+///
+/// * `g`       - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`   - the starting blocks address (which hence must be a node of g);
+/// * `order`   - the order of the block's addresses we would like to test/compare;
+/// * `unit`    - the unit of the blocks' sizes;
+///
+/// # Errors
+///
+/// The same as for cfg_cost.
+///
+pub fn cfg_cost_in<G>(
+    g: G,
+    entry: G::NodeId,
+    order: &[G::NodeId],
+    unit: SizeUnit,
+) -> Result<CfgOrder<G::NodeId>, CostError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord + Default,
+{
+    cfg_cost_with(g, entry, order, &Objective::Distance(unit))
+}
+
+/// The same as cfg_cost, but the cost of the orders is measured by the given objective:
+/// either the total length of the jumps (as cfg_cost and cfg_cost_in do) or the memory
+/// footprint of the hot blocks for a given base address and cache geometry (see CacheCost).
+///
+/// # Arguments
+///
+/// * `g`           - the control flow graph (satisfying several natural traits from petgraph);
+/// * `entry`       - the starting blocks address (which hence must be a node of g);
+/// * `order`       - the order of the block's addresses we would like to test/compare;
+/// * `objective`   - the cost of the orders;
+///
+/// # Errors
+///
+/// The same as for cfg_cost.
+///
+pub fn cfg_cost_with<G>(
+    g: G,
+    entry: G::NodeId,
+    order: &[G::NodeId],
+    objective: &Objective,
+) -> Result<CfgOrder<G::NodeId>, CostError>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoNeighborsDirected + NodeWeight<Node = G::NodeId>,
+    <G as GraphBase>::NodeId: Copy + Eq + Debug + Hash + Ord + Default,
+{
+    let vag: VirtualAddressGraph<G::NodeId> = to_vag(g, entry).map_err(CostError::InvalidGraph)?;
 
     // the original order of the nodes is the ascending order of the ids
     let mut original_order: Vec<G::NodeId> = Vec::new();
@@ -171,13 +606,21 @@ where
         Ordering::Less => Err(CostError::MoreNodesthanOriginal),
         Ordering::Greater => Err(CostError::LessNodesThanOriginal),
         Ordering::Equal => {
+            // every node of the graph is in the order exactly once
+            let nodes: BTreeSet<Vertex<G::NodeId>> = order.iter().map(|&x| Vertex::Id(x)).collect();
+            if nodes.len() != order.len() || nodes.iter().any(|x| !vag.nodes().contains_key(x)) {
+                return Err(CostError::InvalidOrder);
+            }
+
             let kendall_tau = tau_b(&original_order, order).unwrap().0;
-            let original_cost: usize = vag.cost_of_order(&original_order);
-            let sorted_cost: usize = vag.cost_of_order(order);
+            let original_cost: usize = vag.cost_of_order_with(&original_order, objective);
+            let sorted_cost: usize = vag.cost_of_order_with(order, objective);
 
             Ok(CfgOrder {
                 entry: entry,
                 order: order.to_vec(),
+                encoding: vag.encoding_of_order(order),
+                original_encoding: vag.encoding_of_order(&original_order),
                 original_order: original_order,
                 cost: sorted_cost,
                 original_cost: original_cost,
@@ -198,6 +641,8 @@ where
 /// * `original_order`  - the original order of the blocks (ascending by addresses);
 /// * `cost`            - the cost of the given order;
 /// * `original_cost`   - the cost of the original order;
+/// * `encoding`        - the code size and taken branches of the given order (see EncodingCost);
+/// * `original_encoding` - the code size and taken branches of the original order;
 /// * `kendall_tau`     - it measures the difference between two orders (in our case the original
 ///                       and the given), it's a real number between -1 and +1, where -1 means the
 ///                       given order is reversing the original, menawhile +1 means that the two orders
@@ -210,6 +655,8 @@ pub struct CfgOrder<N> {
     original_order: Vec<N>,
     cost: usize,
     original_cost: usize,
+    encoding: EncodingCost,
+    original_encoding: EncodingCost,
     kendall_tau: f64,
 }
 
@@ -220,6 +667,41 @@ where
     pub fn is_better(&self) -> bool {
         self.cost <= self.original_cost
     }
+
+    // the given order
+    pub fn order(&self) -> &[N] {
+        &self.order
+    }
+
+    // the original order of the blocks (ascending by addresses)
+    pub fn original_order(&self) -> &[N] {
+        &self.original_order
+    }
+
+    // how much the given order differs from the original one (see kendall_tau above)
+    pub fn kendall_tau(&self) -> f64 {
+        self.kendall_tau
+    }
+
+    // the cost of the given order
+    pub fn cost(&self) -> usize {
+        self.cost
+    }
+
+    // the cost of the original order
+    pub fn original_cost(&self) -> usize {
+        self.original_cost
+    }
+
+    // the code size and taken branches of the given order
+    pub fn encoding(&self) -> EncodingCost {
+        self.encoding
+    }
+
+    // the code size and taken branches of the original order
+    pub fn original_encoding(&self) -> EncodingCost {
+        self.original_encoding
+    }
 }
 
 impl<N: Display> Display for CfgOrder<N>
@@ -234,6 +716,8 @@ where
         }
         writeln!(f, "kendall tau: {:#?}", self.kendall_tau).ok();
         writeln!(f, "cost of original order: {}", self.original_cost).ok();
+        writeln!(f, "encoding of original order: {}", self.original_encoding).ok();
+        writeln!(f, "encoding of topological sort: {}", self.encoding).ok();
         writeln!(f, "cost of topological sort: {} \n", self.cost)
     }
 }
@@ -241,6 +725,9 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::vagraph::cache::CacheConfig;
+    use crate::vagraph::tiebreak::*;
+    use std::collections::HashMap;
 
     #[test]
     fn missing_nodes() {
@@ -266,10 +753,718 @@ mod test {
         );
     }
 
+    #[test]
+    fn reproducible_order() {
+        // every block has the same length and most of them the same indegree, and there
+        // are two loops, hence the order only depends on the tie-breaking
+        let yaml = "
+address: 0x0
+nodes:
+  - { address: 0x0, len: 1, targets: [0x1, 0x2, 0x3], indegree: 0 }
+  - { address: 0x1, len: 1, targets: [0x4], indegree: 1 }
+  - { address: 0x2, len: 1, targets: [0x4, 0x5], indegree: 2 }
+  - { address: 0x3, len: 1, targets: [0x5], indegree: 1 }
+  - { address: 0x4, len: 1, targets: [0x6], indegree: 2 }
+  - { address: 0x5, len: 1, targets: [0x6, 0x2], indegree: 2 }
+  - { address: 0x6, len: 1, targets: [0x7, 0x0], indegree: 2 }
+  - { address: 0x7, len: 1, targets: [], indegree: 1 }
+";
+        let sorted = || {
+            let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(yaml).unwrap();
+            let vag = unwrapped.to_vag();
+            [Layout::Kahn, Layout::PettisHansen]
+                .map(|layout| cfg_sort_with(&vag, Vertex::Id(0x0), layout).unwrap())
+        };
+
+        let expected = sorted();
+        for _ in 0..100 {
+            assert_eq!(sorted(), expected);
+        }
+    }
+
+    // a graph whose blocks only know their lengths (no profile, no fall-throughs)
+    impl NodeWeight for &petgraph::Graph<usize, ()> {
+        type Node = petgraph::graph::NodeIndex;
+
+        fn weight(&self, node: Self::Node) -> usize {
+            self[node]
+        }
+    }
+
+    #[test]
+    fn lengths_only() {
+        // a diamond whose longer branch is placed first
+        let mut g: petgraph::Graph<usize, ()> = petgraph::Graph::new();
+        let [a, b, c, d] = [1, 2, 5, 1].map(|len| g.add_node(len));
+        g.extend_with_edges([(a, b), (a, c), (b, d), (c, d)]);
+
+        assert_eq!(cfg_sort(&g, a).unwrap(), [a, c, b, d]);
+        for layout in [Layout::Kahn, Layout::PettisHansen, Layout::Fallthrough] {
+            let order = cfg_sort_with(&g, a, layout).unwrap();
+            assert_eq!((order.len(), order[0]), (4, a));
+        }
+    }
+
+    #[test]
+    fn tie_breaks() {
+        // the three blocks after the entry are ready to be placed at the same time
+        let yaml = "
+address: 0x0
+nodes:
+  - { address: 0x0, len: 1, fallthrough: 0x1, weights: { 0x1: 1, 0x2: 50, 0x3: 5 }, targets: [0x1, 0x2, 0x3], indegree: 0 }
+  - { address: 0x1, len: 2, targets: [0x4], indegree: 1 }
+  - { address: 0x2, len: 1, targets: [0x4], indegree: 1 }
+  - { address: 0x3, len: 3, targets: [0x4], indegree: 1 }
+  - { address: 0x4, len: 1, targets: [], indegree: 3 }
+";
+        let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(yaml).unwrap();
+        let vag = unwrapped.to_vag();
+        let entry = Vertex::Id(0x0);
+        let sorted = |tie_break: &dyn TieBreak<Vertex<u64>>| {
+            cfg_sort_by(&vag, entry, tie_break)
+                .unwrap()
+                .iter()
+                .map(|x| x.id().unwrap())
+                .collect::<Vec<u64>>()
+        };
+
+        // the longest block first
+        assert_eq!(sorted(&DegreeLength), [0x0, 0x3, 0x1, 0x2, 0x4]);
+        assert_eq!(
+            cfg_sort(&vag, entry).unwrap(),
+            cfg_sort_by(&vag, entry, &DegreeLength).unwrap()
+        );
+        assert_eq!(sorted(&AddressOrder), [0x0, 0x1, 0x2, 0x3, 0x4]);
+        // the heaviest edge from the entry, then the longest block
+        assert_eq!(sorted(&IncomingWeight), [0x0, 0x2, 0x3, 0x1, 0x4]);
+        // the fall-through of the entry, then the longest block
+        assert_eq!(sorted(&PreferFallthrough), [0x0, 0x1, 0x3, 0x2, 0x4]);
+
+        // any comparator: the largest address first
+        let descending =
+            |_: Option<&NoInstrBasicBlock<Vertex<u64>>>,
+             a: &NoInstrBasicBlock<Vertex<u64>>,
+             b: &NoInstrBasicBlock<Vertex<u64>>| { a.address().cmp(&b.address()) };
+        assert_eq!(sorted(&descending), [0x0, 0x3, 0x2, 0x1, 0x4]);
+
+        // the same comparison as a closure: searched for in every step instead of a heap
+        let degree_length =
+            |last: Option<&NoInstrBasicBlock<Vertex<u64>>>,
+             a: &NoInstrBasicBlock<Vertex<u64>>,
+             b: &NoInstrBasicBlock<Vertex<u64>>| { DegreeLength.compare(last, a, b) };
+        assert!(!TieBreak::<Vertex<u64>>::uses_last(&DegreeLength));
+        assert!(degree_length.uses_last());
+        assert_eq!(sorted(&degree_length), sorted(&DegreeLength));
+    }
+
+    #[test]
+    fn fallthrough_layout() {
+        // the entry falls through to 0x1, which falls through to 0x3
+        let yaml = "
+address: 0x0
+nodes:
+  - { address: 0x0, len: 1, fallthrough: 0x1, targets: [0x1, 0x2], indegree: 0 }
+  - { address: 0x1, len: 1, fallthrough: 0x3, targets: [0x3], indegree: 1 }
+  - { address: 0x2, len: 3, fallthrough: 0x4, targets: [0x4], indegree: 1 }
+  - { address: 0x3, len: 4, fallthrough: 0x4, targets: [0x4], indegree: 1 }
+  - { address: 0x4, len: 1, targets: [], indegree: 2 }
+";
+        let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(yaml).unwrap();
+        let vag = unwrapped.to_vag();
+        let entry = Vertex::Id(0x0);
+
+        // Kahn's heuristic breaks the chain for the longer block, the layout follows it
+        assert_eq!(
+            cfg_sort_with(&vag, entry, Layout::Kahn).unwrap(),
+            [0x0, 0x2, 0x1, 0x3, 0x4].map(Vertex::Id).to_vec()
+        );
+        let order = cfg_sort_with(&vag, entry, Layout::Fallthrough).unwrap();
+        assert_eq!(order, [0x0, 0x1, 0x3, 0x2, 0x4].map(Vertex::Id).to_vec());
+        assert_eq!(cfg_sort_by(&vag, entry, &FollowSuccessor).unwrap(), order);
+
+        // the hottest edge wins over the fall-through, and without a ready successor the
+        // heuristic decides
+        let hot = yaml.replace(
+            "fallthrough: 0x1,",
+            "fallthrough: 0x1, weights: { 0x1: 1, 0x2: 100 },",
+        );
+        let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(&hot).unwrap();
+        let vag = unwrapped.to_vag();
+        assert_eq!(
+            cfg_sort_with(&vag, entry, Layout::Fallthrough).unwrap(),
+            [0x0, 0x2, 0x1, 0x3, 0x4].map(Vertex::Id).to_vec()
+        );
+    }
+
+    #[test]
+    fn fallthrough_after_loop() {
+        // the loop 0x1 <-> 0x2 is left from both of its blocks, 0x2 falls through to 0x3
+        let yaml = "
+address: 0x0
+nodes:
+  - { address: 0x0, len: 1, fallthrough: 0x1, targets: [0x1], indegree: 0 }
+  - { address: 0x1, len: 1, fallthrough: 0x2, targets: [0x2, 0x4], indegree: 2 }
+  - { address: 0x2, len: 1, fallthrough: 0x3, targets: [0x1, 0x3], indegree: 1 }
+  - { address: 0x3, len: 1, targets: [0x5], indegree: 1 }
+  - { address: 0x4, len: 5, fallthrough: 0x5, targets: [0x5], indegree: 1 }
+  - { address: 0x5, len: 1, targets: [], indegree: 2 }
+";
+        let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(yaml).unwrap();
+        let vag = unwrapped.to_vag();
+        let entry = Vertex::Id(0x0);
+
+        // the loop's exits are both ready after it: Kahn's heuristic takes the longer one,
+        // the layout takes the fall-through of the loop's last block
+        assert_eq!(
+            cfg_sort_with(&vag, entry, Layout::Kahn).unwrap(),
+            [0x0, 0x1, 0x2, 0x4, 0x3, 0x5].map(Vertex::Id).to_vec()
+        );
+        assert_eq!(
+            cfg_sort_with(&vag, entry, Layout::Fallthrough).unwrap(),
+            [0x0, 0x1, 0x2, 0x3, 0x4, 0x5].map(Vertex::Id).to_vec()
+        );
+    }
+
+    #[test]
+    fn sort_many() {
+        fn send_sync<T: Send + Sync>() {}
+        send_sync::<VirtualAddressGraph<u64>>();
+
+        let diamond = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 1, &[0x1, 0x2]),
+                (0x1, 2, &[0x3]),
+                (0x2, 3, &[0x3]),
+                (0x3, 1, &[]),
+            ],
+        );
+        let chain = vag_from_blocks(0x10, &[(0x10, 2, &[0x11]), (0x11, 1, &[])]);
+        let graphs = [
+            (&diamond, Vertex::Id(0x0)),
+            (&chain, Vertex::Id(0x10)),
+            (&diamond, Vertex::Id(0x4)),
+        ];
+
+        // the results are in the order of the graphs, whatever the number of threads is
+        let expected = vec![
+            cfg_sort(&diamond, Vertex::Id(0x0)),
+            cfg_sort(&chain, Vertex::Id(0x10)),
+            Err(SortError::InvalidInitialAddress),
+        ];
+        for threads in [1, 2, 0] {
+            assert_eq!(cfg_sort_many(&graphs, Layout::Kahn, threads), expected);
+        }
+    }
+
+    #[test]
+    fn pettis_hansen_hot_loop() {
+        // the loop 0x1 <-> 0x2 is hot, hence 0x1 -> 0x2 must become a fall-through
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 3, &[0x1, 0x3]),
+                (0x1, 2, &[0x2]),
+                (0x2, 4, &[0x1, 0x3]),
+                (0x3, 1, &[0x0]),
+            ],
+        );
+        let entry = Vertex::Id(0x0);
+
+        let order = cfg_sort_with(&vag, entry, Layout::PettisHansen).unwrap();
+        assert_eq!(
+            order,
+            [0x0, 0x1, 0x2, 0x3].map(Vertex::Id).to_vec(),
+            "the entry must stay first and the hot edges must be fall-throughs"
+        );
+
+        // the orders of both layouts can be compared side by side
+        let kahn = cfg_sort_with(&vag, entry, Layout::Kahn).unwrap();
+        assert_eq!(kahn, cfg_sort(&vag, entry).unwrap());
+        assert!(cfg_cost(&vag, entry, &order).is_ok());
+        assert!(cfg_cost(&vag, entry, &kahn).is_ok());
+    }
+
+    #[test]
+    fn profile_weights() {
+        // the diamond 0x0 -> {0x1, 0x2} -> 0x3, where the path through 0x2 is hot
+        let yaml = "
+address: 0x0
+nodes:
+  - { address: 0x0, len: 1, count: 101, weights: { 0x1: 1, 0x2: 100 }, targets: [0x1, 0x2], indegree: 0 }
+  - { address: 0x1, len: 2, count: 1, weights: { 0x3: 1 }, targets: [0x3], indegree: 1 }
+  - { address: 0x2, len: 3, count: 100, weights: { 0x3: 100 }, targets: [0x3], indegree: 1 }
+  - { address: 0x3, len: 1, count: 101, targets: [], indegree: 2 }
+";
+        let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(yaml).unwrap();
+        let vag = unwrapped.to_vag();
+        let entry = Vertex::Id(0x0);
+        assert_eq!(vag.node_at_target(Vertex::Id(0x2)).count(), Some(100));
+        assert_eq!((&vag).edge_weight(entry, Vertex::Id(0x2)), Some(100));
+
+        // the hot edges become fall-throughs
+        let order = cfg_sort_with(&vag, entry, Layout::PettisHansen).unwrap();
+        assert_eq!(order, [0x0, 0x2, 0x3, 0x1].map(Vertex::Id).to_vec());
+        assert_eq!(optimal_order(&vag, entry).unwrap(), order);
+
+        // every jump is weighted by its frequency: 0x0 -> 0x1 jumps over 0x2 and 0x3 once,
+        // 0x1 -> 0x3 jumps back over 0x3 and 0x1 once
+        assert_eq!(
+            cfg_cost(&vag, entry, &order).unwrap().cost(),
+            (3 + 1) + (1 + 2)
+        );
+        // the other way around the hot edges are jumps taken 100 times
+        let cold = [0x0, 0x1, 0x3, 0x2].map(Vertex::Id);
+        assert_eq!(
+            cfg_cost(&vag, entry, &cold).unwrap().cost(),
+            100 * (2 + 1) + 100 * (1 + 3)
+        );
+    }
+
+    #[test]
+    fn hot_cold_split() {
+        // the diamond 0x0 -> {0x1, 0x2} -> 0x3, where 0x1 is never executed
+        let yaml = "
+address: 0x0
+nodes:
+  - { address: 0x0, len: 1, count: 100, weights: { 0x1: 0, 0x2: 100 }, targets: [0x1, 0x2], indegree: 0 }
+  - { address: 0x1, len: 2, targets: [0x3], indegree: 1 }
+  - { address: 0x2, len: 3, count: 100, weights: { 0x3: 100 }, targets: [0x3], indegree: 1 }
+  - { address: 0x3, len: 1, count: 100, targets: [], indegree: 2 }
+";
+        let unwrapped: UnwrappedVAGraph<u64> = serde_yaml::from_str(yaml).unwrap();
+        let vag = unwrapped.to_vag();
+        let entry = Vertex::Id(0x0);
+
+        let split = cfg_sort_split(&vag, entry, Layout::PettisHansen, 0).unwrap();
+        assert_eq!(split.hot, [0x0, 0x2, 0x3].map(Vertex::Id).to_vec());
+        assert_eq!(split.cold, [Vertex::Id(0x1)]);
+        assert_eq!(
+            split.order(),
+            cfg_sort_with(&vag, entry, Layout::PettisHansen).unwrap()
+        );
+
+        // the Kahn layout puts 0x1 in the middle, but it is moved out of the hot region
+        let split = cfg_sort_split(&vag, entry, Layout::Kahn, 0).unwrap();
+        assert_eq!(split.cold, [Vertex::Id(0x1)]);
+        assert_eq!(split.hot.len(), 3);
+
+        // the same errors as cfg_sort
+        assert_eq!(
+            cfg_sort_split(&vag, Vertex::Id(0x4), Layout::Kahn, 0),
+            Err(SortError::InvalidInitialAddress)
+        );
+    }
+
+    // every order of the blocks starting with the entry
+    fn orders_from(entry: u64, blocks: &[u64]) -> Vec<Vec<u64>> {
+        if blocks.is_empty() {
+            return vec![vec![entry]];
+        }
+        let mut orders: Vec<Vec<u64>> = Vec::new();
+        for (i, &block) in blocks.iter().enumerate() {
+            let mut rest = blocks.to_vec();
+            rest.remove(i);
+            for mut order in orders_from(block, &rest) {
+                order.insert(0, entry);
+                orders.push(order);
+            }
+        }
+        orders
+    }
+
+    #[test]
+    fn optimal_order_brute_force() {
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 2, &[0x1, 0x4]),
+                (0x1, 5, &[0x2, 0x3]),
+                (0x2, 1, &[0x1, 0x2]),
+                (0x3, 3, &[0x5, 0x0]),
+                (0x4, 7, &[0x3, 0x5]),
+                (0x5, 2, &[]),
+            ],
+        );
+
+        let optimal = optimal_order(&vag, Vertex::Id(0x0)).unwrap();
+        let optimal: Vec<u64> = optimal.iter().map(|x| x.id().unwrap()).collect();
+        assert_eq!(optimal[0], 0x0);
+
+        let best = orders_from(0x0, &[0x1, 0x2, 0x3, 0x4, 0x5])
+            .iter()
+            .map(|order| vag.cost_of_order(order))
+            .min()
+            .unwrap();
+        assert_eq!(vag.cost_of_order(&optimal), best);
+
+        // the heuristics can not beat the optimum
+        for layout in [Layout::Kahn, Layout::PettisHansen] {
+            let order = cfg_sort_with(&vag, Vertex::Id(0x0), layout).unwrap();
+            let cost = cfg_cost(&vag, Vertex::Id(0x0), &order).unwrap().cost();
+            assert!(best <= cost);
+        }
+    }
+
+    #[test]
+    fn improve_order_local_search() {
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 2, &[0x1, 0x4]),
+                (0x1, 5, &[0x2, 0x3]),
+                (0x2, 1, &[0x1, 0x2]),
+                (0x3, 3, &[0x5, 0x0]),
+                (0x4, 7, &[0x3, 0x5]),
+                (0x5, 2, &[]),
+            ],
+        );
+        let entry = Vertex::Id(0x0);
+        let reversed: Vec<Vertex<u64>> = [0x5, 0x4, 0x3, 0x2, 0x1, 0x0].map(Vertex::Id).to_vec();
+
+        // without budget only the entry is moved to the front
+        let unchanged = improve_order(&vag, entry, &reversed, 0).unwrap();
+        assert_eq!(
+            unchanged,
+            [0x0, 0x5, 0x4, 0x3, 0x2, 0x1].map(Vertex::Id).to_vec()
+        );
+
+        let improved = improve_order(&vag, entry, &reversed, 10_000).unwrap();
+        assert_eq!(improved[0], entry);
+        let before = cfg_cost(&vag, entry, &unchanged).unwrap().cost();
+        let after = cfg_cost(&vag, entry, &improved).unwrap().cost();
+        let optimum = cfg_cost(&vag, entry, &optimal_order(&vag, entry).unwrap())
+            .unwrap()
+            .cost();
+        assert!(optimum <= after && after < before);
+
+        assert_eq!(
+            improve_order(&vag, entry, &reversed[1..], 10),
+            Err(SortError::InvalidOrder)
+        );
+    }
+
+    #[test]
+    fn anneal_order_reproducible() {
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 2, &[0x1, 0x4]),
+                (0x1, 5, &[0x2, 0x3]),
+                (0x2, 1, &[0x1, 0x2]),
+                (0x3, 3, &[0x5, 0x0]),
+                (0x4, 7, &[0x3, 0x5]),
+                (0x5, 2, &[]),
+            ],
+        );
+        let entry = Vertex::Id(0x0);
+        let initial = cfg_sort(&vag, entry).unwrap();
+        let config = AnnealConfig {
+            seed: 0x5eed,
+            iterations: 2_000,
+            ..Default::default()
+        };
+
+        let annealed = anneal_order(&vag, entry, &initial, &config).unwrap();
+        assert_eq!(annealed[0], entry);
+        assert_eq!(
+            annealed,
+            anneal_order(&vag, entry, &initial, &config).unwrap()
+        );
+
+        let before = cfg_cost(&vag, entry, &initial).unwrap().cost();
+        let after = cfg_cost(&vag, entry, &annealed).unwrap().cost();
+        assert!(after <= before);
+
+        // the temperatures out of range are clamped, a zero temperature is a plain descent
+        for (start, end) in [(0.0, 0.0), (-1.0, 0.1), (1.0, 100.0), (f64::NAN, f64::NAN)] {
+            let config = AnnealConfig {
+                start_temperature: start,
+                end_temperature: end,
+                ..config.clone()
+            };
+            let (start, end) = config.temperatures();
+            assert!(0.0 <= end && end <= start);
+
+            let annealed = anneal_order(&vag, entry, &initial, &config).unwrap();
+            assert_eq!(annealed[0], entry);
+            assert!(cfg_cost(&vag, entry, &annealed).unwrap().cost() <= before);
+        }
+    }
+
+    #[test]
+    fn optimal_order_too_many_nodes() {
+        let targets: Vec<[u64; 1]> = (0..=OPTIMAL_MAX_NODES as u64).map(|i| [i + 1]).collect();
+        let blocks: Vec<(u64, usize, &[u64])> = (0..=OPTIMAL_MAX_NODES)
+            .map(|i| (i as u64, 1, &targets[i][..]))
+            .collect();
+        let vag = vag_from_blocks(0x0, &blocks);
+
+        assert_eq!(
+            optimal_order(&vag, Vertex::Id(0x0)),
+            Err(SortError::TooManyNodes)
+        );
+    }
+
+    #[test]
+    fn invalid_cost_order() {
+        let vag = vag_from_blocks(
+            0x0,
+            &[(0x0, 2, &[0x1, 0x2]), (0x1, 3, &[0x2]), (0x2, 1, &[])],
+        );
+        let entry = Vertex::Id(0x0);
+
+        let cost = |order: &[Vertex<u64>]| cfg_cost(&vag, entry, order);
+        assert!(matches!(
+            cost(&[0x0, 0x1].map(Vertex::Id)),
+            Err(CostError::LessNodesThanOriginal)
+        ));
+        assert!(matches!(
+            cost(&[0x0, 0x1, 0xdead].map(Vertex::Id)),
+            Err(CostError::InvalidOrder)
+        ));
+        assert!(matches!(
+            cost(&[0x0, 0x1, 0x1].map(Vertex::Id)),
+            Err(CostError::InvalidOrder)
+        ));
+        assert!(matches!(
+            cfg_cost(&vag, Vertex::Id(0xdead), &[0x0, 0x1, 0x2].map(Vertex::Id)),
+            Err(CostError::InvalidGraph(SortError::InvalidInitialAddress))
+        ));
+        assert!(cost(&[0x2, 0x0, 0x1].map(Vertex::Id)).is_ok());
+    }
+
+    #[test]
+    fn cost_in_bytes() {
+        let vag = vag_from_blocks(
+            0x0,
+            &[(0x0, 2, &[0x1, 0x2]), (0x1, 3, &[0x2]), (0x2, 1, &[])],
+        );
+        let entry = Vertex::Id(0x0);
+        // 0x0 -> 0x1 jumps over 0x2, 0x1 -> 0x2 jumps back over 0x2 and 0x1
+        let order = [0x0, 0x2, 0x1].map(Vertex::Id);
+
+        // without known byte sizes the number of instructions is used
+        let instructions = cfg_cost_in(&vag, entry, &order, SizeUnit::Instructions).unwrap();
+        let bytes = cfg_cost_in(&vag, entry, &order, SizeUnit::Bytes).unwrap();
+        assert_eq!(instructions.cost(), 1 + (1 + 3));
+        assert_eq!(bytes.cost(), 1 + (1 + 3));
+
+        let sizes: HashMap<Vertex<u64>, usize> = HashMap::from([
+            (Vertex::Id(0x0), 7),
+            (Vertex::Id(0x1), 12),
+            (Vertex::Id(0x2), 5),
+        ]);
+        let vag = VirtualAddressGraph::new(
+            entry,
+            vag.nodes()
+                .iter()
+                .map(|(id, node)| (*id, node.clone().with_bytes(sizes[id])))
+                .collect(),
+        );
+
+        let bytes = cfg_cost_in(&vag, entry, &order, SizeUnit::Bytes).unwrap();
+        assert_eq!(bytes.cost(), 5 + (5 + 12));
+        assert_eq!(cfg_cost(&vag, entry, &order).unwrap().cost(), 1 + (1 + 3));
+    }
+
+    #[test]
+    fn cache_objective() {
+        // the loop 0x2 <-> 0x3 is hot, 0x1 is a large cold block (sizes are in bytes)
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 8, &[0x1, 0x2]),
+                (0x1, 120, &[0x2]),
+                (0x2, 24, &[0x3]),
+                (0x3, 24, &[0x2, 0x4]),
+                (0x4, 8, &[]),
+            ],
+        );
+        let entry = Vertex::Id(0x0);
+        let original = [0x0, 0x1, 0x2, 0x3, 0x4].map(Vertex::Id);
+        let config = CacheConfig {
+            base: 40,
+            ..Default::default()
+        };
+
+        // the code starts at 48: the loop occupies [176, 224), two lines, and the jump
+        // back from 0x3 crosses a line boundary
+        let cache = vag.cache_of_order(&original.map(|x| x.id().unwrap()), &config);
+        assert_eq!(
+            (cache.lines, cache.pages, cache.crossing_branches),
+            (2, 1, 1)
+        );
+
+        // a single line and a single page is the best possible
+        let objective = Objective::Cache(config);
+        let improved = improve_order_with(&vag, entry, &original, 10_000, &objective).unwrap();
+        let cost = cfg_cost_with(&vag, entry, &improved, &objective).unwrap();
+        assert_eq!((cost.original_cost(), cost.cost()), (4, 2));
+
+        let annealed = anneal_order_with(
+            &vag,
+            entry,
+            &original,
+            &AnnealConfig {
+                iterations: 5_000,
+                ..Default::default()
+            },
+            &objective,
+        )
+        .unwrap();
+        let cost = cfg_cost_with(&vag, entry, &annealed, &objective).unwrap();
+        assert_eq!(cost.cost(), 2);
+    }
+
+    #[test]
+    fn hot_threshold() {
+        // a profiled chain whose edges get colder and colder
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 1, &[0x1]),
+                (0x1, 1, &[0x2]),
+                (0x2, 1, &[0x3]),
+                (0x3, 1, &[0x4]),
+                (0x4, 1, &[]),
+            ],
+        );
+        let weights: HashMap<Vertex<u64>, usize> = HashMap::from([
+            (Vertex::Id(0x0), 100),
+            (Vertex::Id(0x1), 90),
+            (Vertex::Id(0x2), 40),
+            (Vertex::Id(0x3), 5),
+        ]);
+        let vag = VirtualAddressGraph::new(
+            Vertex::Id(0x0),
+            vag.nodes()
+                .iter()
+                .map(|(id, node)| match node.targets().first() {
+                    Some(&target) => (*id, node.clone().with_edge_weight(target, weights[id])),
+                    None => (*id, node.clone()),
+                })
+                .collect(),
+        );
+        let hot = |hot_percent: u64| -> Vec<u64> {
+            let config = CacheConfig {
+                hot_percent,
+                ..Default::default()
+            };
+            vag.hot_blocks(&config)
+                .iter()
+                .map(|x| x.id().unwrap())
+                .collect()
+        };
+
+        // by default the edges of at least half of the heaviest weight are hot
+        assert_eq!(hot(CacheConfig::default().hot_percent), [0x0, 0x1, 0x2]);
+        assert_eq!(hot(100), [0x0, 0x1]);
+        assert_eq!(hot(40), [0x0, 0x1, 0x2, 0x3]);
+        assert_eq!(hot(0), [0x0, 0x1, 0x2, 0x3, 0x4]);
+    }
+
+    #[test]
+    fn encoding_of_order() {
+        // 0x0: 10 bytes + jcc rel8 (fall-through: 0x1), 0x1: 20 bytes + jmp rel8 to 0x3,
+        // 0x2: 32 bytes falling through to 0x3, 0x3: 4 bytes ending with ret
+        let blocks: [(u64, usize, &[u64]); 4] = [
+            (0x0, 3, &[0x1, 0x2]),
+            (0x1, 5, &[0x3]),
+            (0x2, 7, &[0x3]),
+            (0x3, 1, &[]),
+        ];
+        let vag = vag_from_blocks(0x0, &blocks);
+        let vag = VirtualAddressGraph::new(
+            Vertex::Id(0x0),
+            vag.nodes()
+                .iter()
+                .map(|(id, node)| {
+                    let bytes = [12, 22, 32, 4][id.id().unwrap() as usize];
+                    let mut node = node.clone().with_bytes(bytes);
+                    if *id == Vertex::Id(0x0) || *id == Vertex::Id(0x1) {
+                        node = node.with_branch(2);
+                    }
+                    if *id == Vertex::Id(0x0) {
+                        node = node.with_fallthrough(Vertex::Id(0x1));
+                    }
+                    (*id, node)
+                })
+                .collect(),
+        );
+
+        // the original layout: nothing changes
+        let original = vag.encoding_of_order(&[0x0, 0x1, 0x2, 0x3]);
+        assert_eq!(original.code_size, 12 + 22 + 32 + 4);
+        assert_eq!((original.jumps, original.conditionals), (1, 1));
+        assert_eq!((original.removed_jumps, original.inverted), (0, 0));
+
+        // 0x0 -> 0x2 becomes the fall-through (inverted), 0x1 still needs its jump
+        let sorted = vag.encoding_of_order(&[0x0, 0x2, 0x3, 0x1]);
+        assert_eq!(sorted.inverted, 1);
+        assert_eq!(sorted.taken_branches, 2);
+        assert_eq!(sorted.code_size, 12 + 32 + 4 + 22);
+
+        // 0x0 needs both a jcc and a jmp, and every jump over 0x2 is too far for rel8
+        let vag = VirtualAddressGraph::new(
+            Vertex::Id(0x0),
+            vag.nodes()
+                .iter()
+                .map(|(id, node)| match id.id().unwrap() {
+                    0x2 => (*id, node.clone().with_bytes(200)),
+                    _ => (*id, node.clone()),
+                })
+                .collect(),
+        );
+        let far = vag.encoding_of_order(&[0x0, 0x3, 0x2, 0x1]);
+        assert_eq!(far.taken_branches, 4);
+        assert_eq!(far.near_branches, 3);
+        assert_eq!(far.code_size, (10 + 2 + 5) + 4 + (200 + 5) + (20 + 5));
+    }
+
+    #[test]
+    fn encoding_of_jumps() {
+        let code: Vec<u8> = vec![
+            0x85, 0xff, // 0x1000: test edi, edi
+            0x74, 0x07, // 0x1002: je 0x100b
+            0x31, 0xc0, // 0x1004: xor eax, eax
+            0xe9, 0x05, 0x00, 0x00, 0x00, // 0x1006: jmp 0x1010 (rel32)
+            0xff, 0xc0, // 0x100b: inc eax
+            0xeb, 0x01, // 0x100d: jmp 0x1010 (rel8)
+            0xcc, // 0x100f: int3
+            0xc3, // 0x1010: ret
+        ];
+        let binary = crate::binary::Binary::from_code(0x1000, code);
+        let cfg = crate::cfg::ControlFlowGraph::from_address(&binary, 0x1000);
+        let vag = VirtualAddressGraph::from_cfg(&cfg);
+
+        // the blocks closed by a jmp still reach the next block (the int3 is explored too)
+        assert_eq!(vag.node_at_target(Vertex::Id(0x1004)).targets().len(), 2);
+        assert_eq!(
+            vag.node_at_target(Vertex::Id(0x1004)).jump(),
+            Some(Vertex::Id(0x1010))
+        );
+        assert_eq!(vag.node_at_target(Vertex::Id(0x1000)).jump(), None);
+
+        // the original layout: one jcc and two jmps (shortened to rel8)
+        let original = vag.encoding_of_order(&[0x1000, 0x1004, 0x100b, 0x100f, 0x1010]);
+        assert_eq!((original.jumps, original.conditionals), (2, 1));
+        assert_eq!((original.removed_jumps, original.inverted), (0, 0));
+        assert_eq!(original.code_size, 4 + 4 + 4 + 1 + 1);
+
+        // the jmp of 0x1004 is deleted, the jcc of 0x1000 is inverted
+        let order = [0x1000, 0x100b, 0x100f, 0x1004, 0x1010].map(Vertex::Id);
+        let cost = cfg_cost_in(&vag, vag.address(), &order, SizeUnit::Bytes).unwrap();
+        let encoding = cost.encoding();
+        assert_eq!((encoding.jumps, encoding.conditionals), (1, 1));
+        assert_eq!((encoding.removed_jumps, encoding.inverted), (1, 1));
+        assert_eq!(encoding.code_size, 4 + 4 + 1 + 2 + 1);
+    }
+
     #[test]
     fn empty_graph() {
         let entry: Vertex<u64> = Vertex::Id(0x0);
-        let vag: VirtualAddressGraph<u64> = VirtualAddressGraph::new(entry, HashMap::new());
+        let vag: VirtualAddressGraph<u64> = VirtualAddressGraph::new(entry, BTreeMap::new());
         assert_eq!(
             to_vag(&vag, entry).is_err_and(|x| x == SortError::EmptyGraph),
             true
@@ -279,15 +1474,15 @@ mod test {
     #[test]
     fn not_connected() {
         let address: Vertex<u64> = Vertex::Id(0x0);
-        let mut nodes: HashMap<Vertex<u64>, NoInstrBasicBlock<u64>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<u64>, NoInstrBasicBlock<u64>> = BTreeMap::new();
 
         nodes.insert(
             Vertex::Id(0x0),
             NoInstrBasicBlock::new(
                 Vertex::Id(0x0),
                 1,
-                std::collections::HashSet::<Vertex<u64>>::new(),
-                std::collections::HashSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
                 0,
             ),
         );
@@ -297,8 +1492,8 @@ mod test {
             NoInstrBasicBlock::new(
                 Vertex::Id(0x1),
                 10,
-                std::collections::HashSet::<Vertex<u64>>::new(),
-                std::collections::HashSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
                 0,
             ),
         );
@@ -317,15 +1512,15 @@ mod test {
     #[test]
     fn invalid_entry_address() {
         let address: Vertex<u64> = Vertex::Id(0x3);
-        let mut nodes: HashMap<Vertex<u64>, NoInstrBasicBlock<u64>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<u64>, NoInstrBasicBlock<u64>> = BTreeMap::new();
 
         nodes.insert(
             Vertex::Id(0x0),
             NoInstrBasicBlock::new(
                 Vertex::Id(0x0),
                 1,
-                std::collections::HashSet::<Vertex<u64>>::new(),
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x1), Vertex::Id(0x2)]),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x1), Vertex::Id(0x2)]),
                 0,
             ),
         );
@@ -335,8 +1530,8 @@ mod test {
             NoInstrBasicBlock::new(
                 Vertex::Id(0x1),
                 10,
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x1)]),
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x2)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x1)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x2)]),
                 1,
             ),
         );
@@ -346,8 +1541,8 @@ mod test {
             NoInstrBasicBlock::new(
                 Vertex::Id(0x2),
                 5,
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x0), Vertex::Id(0x1)]),
-                std::collections::HashSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x0), Vertex::Id(0x1)]),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
                 2,
             ),
         );
@@ -363,15 +1558,15 @@ mod test {
     #[test]
     fn filtered_targets_two_nodes() {
         let address: Vertex<u64> = Vertex::Id(0x0);
-        let mut nodes: HashMap<Vertex<u64>, NoInstrBasicBlock<u64>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<u64>, NoInstrBasicBlock<u64>> = BTreeMap::new();
 
         nodes.insert(
             Vertex::Id(0x0),
             NoInstrBasicBlock::new(
                 Vertex::Id(0x0),
                 1,
-                std::collections::HashSet::<Vertex<u64>>::new(),
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x1)]),
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x1)]),
                 0,
             ),
         );
@@ -381,8 +1576,8 @@ mod test {
             NoInstrBasicBlock::new(
                 Vertex::Id(0x1),
                 10,
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x0)]),
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x2)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x0)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x2)]),
                 0,
             ),
         );
@@ -410,15 +1605,15 @@ mod test {
     #[test]
     fn filtered_targets_three_nodes_multiple_phantom_edges() {
         let address: Vertex<u64> = Vertex::Id(0x0);
-        let mut nodes: HashMap<Vertex<u64>, NoInstrBasicBlock<u64>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<u64>, NoInstrBasicBlock<u64>> = BTreeMap::new();
 
         nodes.insert(
             Vertex::Id(0x0),
             NoInstrBasicBlock::new(
                 Vertex::Id(0x0),
                 1,
-                std::collections::HashSet::<Vertex<u64>>::new(),
-                std::collections::HashSet::<Vertex<u64>>::from([
+                std::collections::BTreeSet::<Vertex<u64>>::new(),
+                std::collections::BTreeSet::<Vertex<u64>>::from([
                     Vertex::Id(0x1),
                     Vertex::Id(0x2),
                     Vertex::Id(0x6),
@@ -432,8 +1627,8 @@ mod test {
             NoInstrBasicBlock::new(
                 Vertex::Id(0x1),
                 10,
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x1)]),
-                std::collections::HashSet::<Vertex<u64>>::from([
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x1)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([
                     Vertex::Id(0x2),
                     Vertex::Id(0x7),
                     Vertex::Id(0x9),
@@ -447,8 +1642,8 @@ mod test {
             NoInstrBasicBlock::new(
                 Vertex::Id(0x2),
                 5,
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x0), Vertex::Id(0x1)]),
-                std::collections::HashSet::<Vertex<u64>>::from([Vertex::Id(0x6), Vertex::Id(0x7)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x0), Vertex::Id(0x1)]),
+                std::collections::BTreeSet::<Vertex<u64>>::from([Vertex::Id(0x6), Vertex::Id(0x7)]),
                 2,
             ),
         );
@@ -513,14 +1708,18 @@ mod test {
 /// * `UnreachableNodes`        - from the given entry address we can not reach all the nodes of
 ///                               the control flow graph;
 /// *`InvalidInitialAddress`    - there is no block in g at the given entry address;
+/// * `TooManyNodes`            - g is too large for the exact solver of optimal_order;
+/// * `InvalidOrder`            - the given order is not an order of the nodes of g;
 ///
 /// etc.
 ///
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SortError {
     EmptyGraph,
     UnreachableNodes,
     InvalidInitialAddress,
+    TooManyNodes,
+    InvalidOrder,
 }
 
 impl Display for SortError {
@@ -535,6 +1734,14 @@ impl Display for SortError {
                 f,
                 "Cannot sort graph: the start node is missing from the graph!"
             ),
+            Self::TooManyNodes => write!(
+                f,
+                "Cannot sort graph: it has too many nodes to be sorted optimally!"
+            ),
+            Self::InvalidOrder => write!(
+                f,
+                "Cannot improve order: it is not an order of the graph's nodes!"
+            ),
         }
     }
 }
@@ -547,9 +1754,37 @@ impl Error for SortError {}
 ///
 /// * `LessNodesThanOriginal` - the given order slice contains less nodes than g has;
 /// * `MoreNodesThanOriginal` - the given order slice contains more nodes than g has;
+/// * `InvalidOrder`          - the given order slice contains a node not in g, or one twice;
+/// * `InvalidGraph`          - the graph can not be handled from the given entry (see SortError);
 
-#[derive(Debug)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CostError {
     LessNodesThanOriginal,
     MoreNodesthanOriginal,
+    InvalidOrder,
+    InvalidGraph(SortError),
+}
+
+impl Display for CostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LessNodesThanOriginal => {
+                write!(
+                    f,
+                    "Cannot compare orders: some nodes are missing from the order!"
+                )
+            }
+            Self::MoreNodesthanOriginal => write!(
+                f,
+                "Cannot compare orders: the order has more nodes than the graph!"
+            ),
+            Self::InvalidOrder => write!(
+                f,
+                "Cannot compare orders: it is not an order of the graph's nodes!"
+            ),
+            Self::InvalidGraph(err) => write!(f, "Cannot compare orders: {err}"),
+        }
+    }
 }
+
+impl Error for CostError {}
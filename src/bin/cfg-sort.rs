@@ -0,0 +1,386 @@
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use cfg_sort::{
+    cfg_cost_in, cfg_sort_with, evaluate_corpus, BatchConfig, Binary, ControlFlowGraph,
+    EncodingCost, Layout, SizeUnit, UnwrappedVAGraph, Vertex, VirtualAddressGraph,
+};
+
+// the command-line driver of the library: from the functions of a binary (or a corpus of
+// graphs) to the orders of their blocks
+#[derive(Debug, Parser)]
+#[command(
+    name = "cfg-sort",
+    version,
+    about = "Reorders the basic blocks of functions"
+)]
+struct Cli {
+    /// The format of the output
+    #[arg(long, value_enum, global = true, default_value_t = Format::Text)]
+    format: Format,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+    // note: only the batch evaluation is written as CSV
+    Csv,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Dumps the basic blocks of a function
+    Cfg(FunctionArgs),
+    /// Renders the control flow graph of a function in the dot format
+    Dot(FunctionArgs),
+    /// Prints the sorted order of the blocks of a function
+    Sort {
+        #[command(flatten)]
+        function: FunctionArgs,
+        #[command(flatten)]
+        sort: SortArgs,
+    },
+    /// Compares an order of the blocks of a function to their original order
+    Cost {
+        #[command(flatten)]
+        function: FunctionArgs,
+        #[command(flatten)]
+        sort: SortArgs,
+        /// The addresses of the blocks in order (the sorted order by default)
+        #[arg(long, value_delimiter = ',', value_parser = parse_address)]
+        order: Option<Vec<u64>>,
+    },
+    /// Sorts every graph of a YAML corpus and compares the orders to the original ones
+    Batch {
+        /// The YAML file of the graphs
+        corpus: PathBuf,
+        #[command(flatten)]
+        sort: SortArgs,
+        /// The number of threads (0: as many as the cores)
+        #[arg(long, default_value_t = 0)]
+        threads: usize,
+    },
+}
+
+#[derive(Debug, Args)]
+struct FunctionArgs {
+    /// The ELF file
+    binary: PathBuf,
+    /// The function: either its (hexadecimal) address or the name of its symbol
+    function: String,
+}
+
+#[derive(Debug, Args)]
+struct SortArgs {
+    /// The layout strategy
+    #[arg(long, value_enum, default_value_t = LayoutArg::Kahn)]
+    layout: LayoutArg,
+    /// The unit of the lengths of the jumps
+    #[arg(long, value_enum, default_value_t = UnitArg::Instructions)]
+    unit: UnitArg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LayoutArg {
+    Kahn,
+    PettisHansen,
+    Fallthrough,
+}
+
+impl From<LayoutArg> for Layout {
+    fn from(layout: LayoutArg) -> Self {
+        match layout {
+            LayoutArg::Kahn => Layout::Kahn,
+            LayoutArg::PettisHansen => Layout::PettisHansen,
+            LayoutArg::Fallthrough => Layout::Fallthrough,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum UnitArg {
+    Instructions,
+    Bytes,
+}
+
+impl From<UnitArg> for SizeUnit {
+    fn from(unit: UnitArg) -> Self {
+        match unit {
+            UnitArg::Instructions => SizeUnit::Instructions,
+            UnitArg::Bytes => SizeUnit::Bytes,
+        }
+    }
+}
+
+// a block of the control flow graph of a function
+#[derive(Debug, Serialize)]
+struct BlockReport {
+    address: u64,
+    end_address: u64,
+    instructions: usize,
+    successors: Vec<u64>,
+}
+
+// the comparison of an order to the original one (see CfgOrder)
+#[derive(Debug, Serialize)]
+struct CostReport {
+    entry: u64,
+    order: Vec<u64>,
+    original_order: Vec<u64>,
+    cost: usize,
+    original_cost: usize,
+    encoding: EncodingCost,
+    original_encoding: EncodingCost,
+    kendall_tau: f64,
+    is_better: bool,
+}
+
+// parses a (hexadecimal, optionally 0x prefixed) address
+fn parse_address(text: &str) -> Result<u64, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(digits, 16).map_err(|err| format!("invalid address {text}: {err}"))
+}
+
+// the control flow graph of the function given by its address or by its symbol
+// note: the library panics on bad input, hence the file and the address are checked first:
+//       the file has to be an ELF file and the address has to be executable code
+fn function_cfg(args: &FunctionArgs) -> Result<ControlFlowGraph, Box<dyn Error>> {
+    if !args.binary.is_file() {
+        return Err(format!("no such file: {}", args.binary.display()).into());
+    }
+    let binary = Binary::try_from_elf(args.binary.to_string_lossy().to_string())?;
+
+    let address = match binary.symbol(&args.function) {
+        Some(symbol) => symbol.address,
+        None => parse_address(&args.function)
+            .map_err(|_| format!("no such function: {}", args.function))?,
+    };
+    if !binary.is_executable(address) {
+        return Err(format!("not an address of executable code: {address:#x}").into());
+    }
+    binary.virtual_address_range(address..address + 1)?;
+
+    Ok(ControlFlowGraph::from_address(&binary, address))
+}
+
+// the sorted order of the blocks of the graph
+fn sorted(vag: &VirtualAddressGraph<u64>, sort: &SortArgs) -> Result<Vec<u64>, Box<dyn Error>> {
+    let order = cfg_sort_with(vag, vag.address(), sort.layout.into())?;
+    Ok(order.iter().filter_map(|x| x.id().ok()).collect())
+}
+
+// the comparison of the given order of the blocks of the graph to the original one
+fn cost(
+    vag: &VirtualAddressGraph<u64>,
+    order: &[u64],
+    sort: &SortArgs,
+) -> Result<CostReport, Box<dyn Error>> {
+    // the library rejects the invalid orders too, but it does not tell which block is wrong
+    if let Some(block) = order
+        .iter()
+        .find(|&&x| !vag.nodes().contains_key(&Vertex::Id(x)))
+    {
+        return Err(format!("not a block of the function: {block:#x}").into());
+    }
+    let order: Vec<Vertex<u64>> = order.iter().map(|&x| Vertex::Id(x)).collect();
+    let cost = cfg_cost_in(vag, vag.address(), &order, sort.unit.into())?;
+    let ids =
+        |order: &[Vertex<u64>]| -> Vec<u64> { order.iter().filter_map(|x| x.id().ok()).collect() };
+
+    Ok(CostReport {
+        entry: vag.address().id()?,
+        order: ids(cost.order()),
+        original_order: ids(cost.original_order()),
+        cost: cost.cost(),
+        original_cost: cost.original_cost(),
+        encoding: cost.encoding(),
+        original_encoding: cost.original_encoding(),
+        kendall_tau: cost.kendall_tau(),
+        is_better: cost.is_better(),
+    })
+}
+
+fn print_cost(report: &CostReport) {
+    println!("entry: {:#x}", report.entry);
+    println!("original order, order");
+    for (original, block) in report.original_order.iter().zip(&report.order) {
+        println!("{original:#x}, {block:#x}");
+    }
+    println!("kendall tau: {}", report.kendall_tau);
+    println!("cost of original order: {}", report.original_cost);
+    println!("cost of order: {}", report.cost);
+    println!("encoding of original order: {}", report.original_encoding);
+    println!("encoding of order: {}", report.encoding);
+    println!("better: {}", report.is_better);
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let json = match cli.format {
+        Format::Text => false,
+        Format::Json => true,
+        Format::Csv if matches!(cli.command, Command::Batch { .. }) => false,
+        Format::Csv => return Err("only the batch evaluation can be written as CSV".into()),
+    };
+
+    match cli.command {
+        Command::Cfg(args) => {
+            let cfg = function_cfg(&args)?;
+            let blocks: Vec<BlockReport> = cfg
+                .blocks()
+                .iter()
+                .map(|b| BlockReport {
+                    address: b.address(),
+                    end_address: b.end_address(),
+                    instructions: b.instructions().len(),
+                    successors: b.successors(),
+                })
+                .collect();
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&blocks)?);
+            } else {
+                for block in &blocks {
+                    println!(
+                        "{:#x}-{:#x}: {} instructions, successors: {:x?}",
+                        block.address, block.end_address, block.instructions, block.successors
+                    );
+                }
+            }
+        }
+        Command::Dot(args) => {
+            let cfg = function_cfg(&args)?;
+            cfg.render_to(&mut std::io::stdout())?;
+        }
+        Command::Sort { function, sort } => {
+            let vag = VirtualAddressGraph::from_cfg(&function_cfg(&function)?);
+            let order = sorted(&vag, &sort)?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&order)?);
+            } else {
+                for block in order {
+                    println!("{block:#x}");
+                }
+            }
+        }
+        Command::Cost {
+            function,
+            sort,
+            order,
+        } => {
+            let vag = VirtualAddressGraph::from_cfg(&function_cfg(&function)?);
+            let order = match order {
+                Some(order) => order,
+                None => sorted(&vag, &sort)?,
+            };
+            let report = cost(&vag, &order, &sort)?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_cost(&report);
+            }
+        }
+        Command::Batch {
+            corpus,
+            sort,
+            threads,
+        } => {
+            let file = std::fs::File::open(&corpus)
+                .map_err(|err| format!("cannot open {}: {err}", corpus.display()))?;
+            let graphs: Vec<UnwrappedVAGraph<u64>> = serde_yaml::from_reader(file)?;
+            let config = BatchConfig {
+                layout: sort.layout.into(),
+                unit: sort.unit.into(),
+                threads,
+            };
+            let report = evaluate_corpus(&graphs, &config);
+
+            let mut output = std::io::stdout().lock();
+            match cli.format {
+                Format::Text => {
+                    for evaluation in &report.evaluations {
+                        match (&evaluation.stats, &evaluation.error) {
+                            (Some(stats), _) => writeln!(
+                                output,
+                                "{:#x}: cost {} -> {}, kendall tau {:.3}",
+                                evaluation.entry,
+                                stats.original_cost,
+                                stats.cost,
+                                stats.kendall_tau
+                            )?,
+                            (None, Some(err)) => {
+                                writeln!(output, "{:#x}: error: {err}", evaluation.entry)?
+                            }
+                            (None, None) => {}
+                        }
+                    }
+                    write!(output, "{}", report.stats)?;
+                }
+                Format::Json => report.write_json(&mut output)?,
+                Format::Csv => report.write_csv(&mut output)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the command line, which has to be valid
+    fn run_args(args: &[&str]) -> Result<(), Box<dyn Error>> {
+        run(Cli::try_parse_from([&["cfg-sort"], args].concat()).unwrap())
+    }
+
+    #[test]
+    fn invalid_input() {
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let test = std::env::current_exe().unwrap();
+        let test = test.to_str().unwrap();
+
+        // not a file, not an ELF file
+        let err = run_args(&["cfg", "/no/such/file", "main"]).unwrap_err();
+        assert!(err.to_string().starts_with("no such file"));
+        let err = run_args(&["cfg", manifest, "main"]).unwrap_err();
+        assert!(err.to_string().contains("as an ELF file"));
+
+        // the start of the file (the ELF header) is not code, and neither is a bad address
+        let err = run_args(&["sort", test, "0x0"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("not an address of executable code"));
+        let err = run_args(&["sort", test, "no_such_function"]).unwrap_err();
+        assert!(err.to_string().starts_with("no such function"));
+
+        // CSV is only written by the batch evaluation
+        let err = run_args(&["--format", "csv", "cfg", test, "0x0"]).unwrap_err();
+        assert!(err.to_string().contains("CSV"));
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_address("0x23d0"), Ok(0x23d0));
+        assert_eq!(parse_address("23D0"), Ok(0x23d0));
+        assert!(parse_address("main").is_err());
+    }
+}
@@ -5,39 +5,134 @@ use std::io::Read;
 
 use std::ops::*;
 
+/// A function symbol of a binary.
+///
+/// # Fields
+///
+/// * `name`    - the name of the symbol (as it is in the symbol table, i.e. mangled);
+/// * `address` - the virtual address of the function;
+/// * `size`    - the size of the function in bytes;
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
 pub struct Binary {
     program_header: Vec<ProgramHeader>,
     bytes: Vec<u8>,
+    // the function symbols ordered by their addresses
+    symbols: Vec<Symbol>,
 }
 
 impl Binary {
     // from path of the exe file to Binary instance
+    // note: it panics if the file can not be read or parsed (see try_from_elf)
     pub fn from_elf(path: String) -> Self {
-        // INITIALIZATION: file read, length
-        let mut file = File::open(path).map_err(|_| "open file error").unwrap();
-        let file_len = file
-            .metadata()
-            .map_err(|_| "get metadata error")
-            .unwrap()
-            .len();
+        Self::try_from_elf(path).unwrap()
+    }
 
+    // from path of the exe file to Binary instance or error: the file can not be read or it
+    // is not an ELF file
+    pub fn try_from_elf(path: String) -> Result<Self, String> {
         // INITIALIZATION: vector of bytes
-        let mut contents = vec![0; file_len as usize];
-        file.read_exact(&mut contents[..])
-            .map_err(|_| "read header error")
-            .unwrap();
+        let mut contents: Vec<u8> = Vec::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut contents))
+            .map_err(|err| format!("cannot read {path}: {err}"))?;
 
         // INITIALIZATION: elf file
         let elf = Elf::parse(&contents[..])
-            .map_err(|_| "cannot parse elf file error")
-            .unwrap();
+            .map_err(|err| format!("cannot parse {path} as an ELF file: {err}"))?;
 
-        Binary {
+        // the function symbols: from the symbol table, or the dynamic one if it is stripped
+        let (syms, strtab) = match elf.syms.is_empty() {
+            true => (&elf.dynsyms, &elf.dynstrtab),
+            false => (&elf.syms, &elf.strtab),
+        };
+        let mut symbols: Vec<Symbol> = syms
+            .iter()
+            .filter(|sym| sym.is_function() && sym.st_value != 0)
+            .filter_map(|sym| {
+                Some(Symbol {
+                    name: strtab.get_at(sym.st_name)?.to_string(),
+                    address: sym.st_value,
+                    size: sym.st_size,
+                })
+            })
+            .collect();
+        symbols.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+        symbols.dedup_by(|a, b| a.name == b.name);
+
+        Ok(Binary {
             program_header: elf.program_headers,
             bytes: contents,
+            symbols,
+        })
+    }
+
+    // from raw machine code loaded at the given virtual address to Binary instance
+    // note: the code is presented as a single loadable segment
+    pub fn from_code(base: u64, code: Vec<u8>) -> Self {
+        let segment = ProgramHeader {
+            p_type: program_header::PT_LOAD,
+            p_offset: 0,
+            p_vaddr: base,
+            p_filesz: code.len() as u64,
+            p_memsz: code.len() as u64,
+            p_flags: program_header::PF_R | program_header::PF_X,
+            ..Default::default()
+        };
+
+        Binary {
+            program_header: vec![segment],
+            bytes: code,
+            symbols: Vec::new(),
         }
     }
 
+    // adds a function symbol (e.g. to the raw machine code of from_code)
+    pub fn with_symbol(mut self, name: &str, address: u64, size: u64) -> Self {
+        let symbol = Symbol {
+            name: name.to_string(),
+            address,
+            size,
+        };
+        let index = self.symbols.partition_point(|x| x.address <= address);
+        self.symbols.insert(index, symbol);
+        self
+    }
+
+    // the function symbols ordered by their addresses
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // the function symbol with the given name (if there is one)
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|x| x.name == name)
+    }
+
+    // the function symbol containing the given virtual address (if there is one)
+    // note: an address in an alias' function is in the first of them
+    pub fn symbol_at(&self, address: u64) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|x| x.address <= address && address < x.address + x.size.max(1))
+    }
+
+    // whether the given virtual address is in an executable loadable segment
+    pub fn is_executable(&self, address: u64) -> bool {
+        self.program_header.iter().any(|x| {
+            x.p_type == program_header::PT_LOAD
+                && x.is_executable()
+                && x.p_vaddr <= address
+                && address < x.p_vaddr + x.p_filesz
+        })
+    }
+
     // slice of bytes at a given virtual address range or error:invalid
     pub fn virtual_address_range<T: RangeBounds<u64>>(&self, range: T) -> Result<&[u8], String> {
         // start bound
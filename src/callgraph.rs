@@ -0,0 +1,545 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::binary::Binary;
+
+// the maximal size of a cluster of the C3 ordering in bytes (the same as in lld)
+const C3_MAX_CLUSTER_SIZE: usize = 1 << 20;
+// a cluster is not merged into its caller's if that would make the density of the caller's
+// cluster drop below its 1 / C3_MAX_DENSITY_DEGRADATION part (the same as in lld)
+const C3_MAX_DENSITY_DEGRADATION: f64 = 8.0;
+
+/// A function of a call graph.
+///
+/// # Fields
+///
+/// * `name`    - the symbol of the function;
+/// * `size`    - the size of the function in bytes;
+/// * `samples` - how many times the function was executed (if not given, the number of calls);
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub size: usize,
+    #[serde(default)]
+    pub samples: Option<usize>,
+}
+
+/// The calls from a function to another one.
+///
+/// # Fields
+///
+/// * `caller`  - the symbol of the calling function;
+/// * `callee`  - the symbol of the called function;
+/// * `count`   - the number of calls (e.g. from a profile);
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub caller: String,
+    pub callee: String,
+    pub count: usize,
+}
+
+/// The strategies to order the functions of a binary.
+///
+/// # Variants
+///
+/// * `C3`              - call-chain clustering: callees are appended to their hottest caller;
+/// * `PettisHansen`    - Pettis and Hansen's merging of the heaviest calls;
+///
+/// Both strategies put the clusters with the most samples per byte first, hence the hot
+/// code is packed into as few pages as possible.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FunctionOrder {
+    #[default]
+    C3,
+    PettisHansen,
+}
+
+/// The functions of a binary with the number of calls between them.
+///
+/// # Fields
+///
+/// * `functions`   - the functions in the order of the binary;
+/// * `calls`       - the calls between the functions (to unknown functions are ignored);
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    pub functions: Vec<Function>,
+    pub calls: Vec<Call>,
+}
+
+// the calls between two clusters: their total count and the heaviest one as a (count, f, g)
+// key, where f < g and the ties are broken by the smaller functions
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    count: usize,
+    heaviest: (usize, Reverse<usize>, Reverse<usize>),
+}
+
+impl Link {
+    // the calls between a cluster and the merge of two others
+    fn join(&self, other: &Link) -> Link {
+        Link {
+            count: self.count + other.count,
+            heaviest: self.heaviest.max(other.heaviest),
+        }
+    }
+}
+
+// the root of the tree of the given element in a union-find forest (halving the path to it)
+fn find(parent: &mut [usize], mut x: usize) -> usize {
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+// a set of functions laid out one after the other
+#[derive(Debug, Clone)]
+struct Cluster {
+    functions: Vec<usize>,
+    size: usize,
+    samples: usize,
+}
+
+impl Cluster {
+    // the samples per byte
+    fn density(&self) -> f64 {
+        self.samples as f64 / self.size.max(1) as f64
+    }
+}
+
+impl CallGraph {
+    // creates an instance from the functions and the calls between them
+    pub fn new(functions: Vec<Function>, calls: Vec<Call>) -> Self {
+        CallGraph { functions, calls }
+    }
+
+    // creates an instance from the function symbols of a binary and the calls between them
+    // note: the samples of the functions are the number of their calls
+    pub fn from_binary(binary: &Binary, calls: Vec<Call>) -> Self {
+        let functions = binary
+            .symbols()
+            .iter()
+            .map(|symbol| Function {
+                name: symbol.name.clone(),
+                size: symbol.size as usize,
+                samples: None,
+            })
+            .collect();
+
+        CallGraph { functions, calls }
+    }
+
+    // the symbols of the functions in the order given by the strategy
+    pub fn order(&self, strategy: FunctionOrder) -> Vec<String> {
+        let clusters = match strategy {
+            FunctionOrder::C3 => self.c3_clusters(),
+            FunctionOrder::PettisHansen => self.pettis_hansen_clusters(),
+        };
+
+        self.by_density(clusters)
+            .iter()
+            .map(|&f| self.functions[f].name.clone())
+            .collect()
+    }
+
+    // the sum of the distances between the starts of the callers and the callees in bytes,
+    // weighted by the number of calls, if the functions are laid out in the given order
+    // note: the functions missing from the order are placed after it
+    pub fn call_distance(&self, order: &[String]) -> usize {
+        let addresses = self.addresses(order);
+
+        self.calls_by_index()
+            .iter()
+            .map(|(&(caller, callee), &count)| {
+                count * addresses[caller].abs_diff(addresses[callee])
+            })
+            .sum()
+    }
+
+    // the number of pages touched by the functions with samples, if the functions are laid
+    // out in the given order from a page boundary
+    pub fn hot_pages(&self, order: &[String], page_size: usize) -> usize {
+        let addresses = self.addresses(order);
+        let samples = self.samples();
+        let page_size = page_size.max(1);
+
+        let pages: HashSet<usize> = (0..self.functions.len())
+            .filter(|&f| samples[f] > 0 && self.functions[f].size > 0)
+            .flat_map(|f| {
+                let (first, last) = (addresses[f], addresses[f] + self.functions[f].size - 1);
+                first / page_size..=last / page_size
+            })
+            .collect();
+
+        pages.len()
+    }
+
+    // the index of every function by its symbol
+    fn indices(&self) -> HashMap<&str, usize> {
+        self.functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.name.as_str(), i))
+            .collect()
+    }
+
+    // the number of calls between the known functions by their indices (without recursion)
+    fn calls_by_index(&self) -> BTreeMap<(usize, usize), usize> {
+        let indices = self.indices();
+        let mut calls: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+
+        for call in &self.calls {
+            let (Some(&caller), Some(&callee)) = (
+                indices.get(call.caller.as_str()),
+                indices.get(call.callee.as_str()),
+            ) else {
+                continue;
+            };
+            if caller != callee {
+                *calls.entry((caller, callee)).or_insert(0) += call.count;
+            }
+        }
+
+        calls
+    }
+
+    // the samples of the functions: the given ones, otherwise the number of calls
+    fn samples(&self) -> Vec<usize> {
+        let mut calls: Vec<usize> = vec![0; self.functions.len()];
+        for (&(_, callee), &count) in &self.calls_by_index() {
+            calls[callee] += count;
+        }
+
+        self.functions
+            .iter()
+            .zip(calls)
+            .map(|(f, calls)| f.samples.unwrap_or(calls))
+            .collect()
+    }
+
+    // the start of every function (by index) in the given order
+    fn addresses(&self, order: &[String]) -> Vec<usize> {
+        let indices = self.indices();
+        let mut placed: Vec<usize> = order
+            .iter()
+            .filter_map(|name| indices.get(name.as_str()).copied())
+            .collect();
+        let seen: HashSet<usize> = placed.iter().copied().collect();
+        placed.extend((0..self.functions.len()).filter(|f| !seen.contains(f)));
+
+        let mut addresses: Vec<usize> = vec![0; self.functions.len()];
+        let mut address: usize = 0;
+        for f in placed {
+            addresses[f] = address;
+            address += self.functions[f].size;
+        }
+
+        addresses
+    }
+
+    // every function in its own cluster
+    fn singletons(&self) -> Vec<Cluster> {
+        self.functions
+            .iter()
+            .zip(self.samples())
+            .enumerate()
+            .map(|(i, (f, samples))| Cluster {
+                functions: vec![i],
+                size: f.size,
+                samples,
+            })
+            .collect()
+    }
+
+    // the functions of the clusters, where the clusters with the most samples per byte come
+    // first (the ties are broken by the order of the binary)
+    fn by_density(&self, mut clusters: Vec<Cluster>) -> Vec<usize> {
+        clusters.retain(|c| !c.functions.is_empty());
+        clusters.sort_by(|a, b| {
+            b.density()
+                .total_cmp(&a.density())
+                .then(a.functions.iter().min().cmp(&b.functions.iter().min()))
+        });
+
+        clusters.into_iter().flat_map(|c| c.functions).collect()
+    }
+
+    // call-chain clustering (Ottoni and Maher): visiting the functions from the hottest one,
+    // the cluster of every function is appended to the cluster of its most frequent caller
+    // note: the merges are limited by the size of the clusters and the drop of the density
+    fn c3_clusters(&self) -> Vec<Cluster> {
+        let mut clusters = self.singletons();
+        let mut cluster_of: Vec<usize> = (0..self.functions.len()).collect();
+
+        // the most frequent caller of every function
+        let mut callers: HashMap<usize, (usize, usize)> = HashMap::new();
+        for (&(caller, callee), &count) in &self.calls_by_index() {
+            let best = callers.entry(callee).or_insert((caller, count));
+            if count > best.1 {
+                *best = (caller, count);
+            }
+        }
+
+        let samples = self.samples();
+        let mut hottest: Vec<usize> = (0..self.functions.len()).collect();
+        hottest.sort_by_key(|&f| (Reverse(samples[f]), f));
+
+        for function in hottest {
+            let Some(&(caller, count)) = callers.get(&function) else {
+                continue;
+            };
+            let (to, from) = (cluster_of[caller], cluster_of[function]);
+            if count == 0 || to == from {
+                continue;
+            }
+
+            let (size, merged) = (
+                clusters[to].size + clusters[from].size,
+                clusters[to].samples + clusters[from].samples,
+            );
+            let density = merged as f64 / size.max(1) as f64;
+            if size > C3_MAX_CLUSTER_SIZE
+                || density * C3_MAX_DENSITY_DEGRADATION < clusters[to].density()
+            {
+                continue;
+            }
+
+            let moved = std::mem::take(&mut clusters[from].functions);
+            for &f in &moved {
+                cluster_of[f] = to;
+            }
+            clusters[to].functions.extend(moved);
+            clusters[to].size = size;
+            clusters[to].samples = merged;
+        }
+
+        clusters
+    }
+
+    // Pettis and Hansen's function ordering: the clusters joined by the most calls (in both
+    // directions) are merged, such that the functions of the heaviest call between them are
+    // as close as possible - the merged cluster inherits the calls of both
+    fn pettis_hansen_clusters(&self) -> Vec<Cluster> {
+        let mut clusters = self.singletons();
+        // the union-find forest of the functions: the root of a tree is the index of the cluster
+        let mut parent: Vec<usize> = (0..self.functions.len()).collect();
+
+        // the undirected calls between the functions
+        let mut calls: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+        for (&(caller, callee), &count) in &self.calls_by_index() {
+            *calls
+                .entry((caller.min(callee), caller.max(callee)))
+                .or_insert(0) += count;
+        }
+
+        // the calls between the clusters, at both of their ends
+        let mut links: Vec<BTreeMap<usize, Link>> = vec![BTreeMap::new(); self.functions.len()];
+        for (&(f, g), &count) in &calls {
+            if f != g && count > 0 {
+                let link = Link {
+                    count,
+                    heaviest: (count, Reverse(f), Reverse(g)),
+                };
+                links[f].insert(g, link);
+                links[g].insert(f, link);
+            }
+        }
+
+        // the heaviest connection between two clusters (the ties by the smaller indices)
+        // note: an entry is outdated if one of the clusters is merged or the count changed since
+        let mut heaviest: BinaryHeap<(usize, Reverse<usize>, Reverse<usize>)> = links
+            .iter()
+            .enumerate()
+            .flat_map(|(a, links)| {
+                links
+                    .range(a + 1..)
+                    .map(move |(&b, link)| (link.count, Reverse(a), Reverse(b)))
+            })
+            .collect();
+
+        while let Some((count, Reverse(to), Reverse(from))) = heaviest.pop() {
+            let Some(link) = links[to].get(&from).filter(|x| x.count == count) else {
+                continue;
+            };
+
+            // the heaviest call between the two clusters decides their orientation
+            let (_, Reverse(f), Reverse(g)) = link.heaviest;
+            let (f, g) = match find(&mut parent, f) == to {
+                true => (f, g),
+                false => (g, f),
+            };
+
+            let moved = std::mem::take(&mut clusters[from].functions);
+            let merged = self.closest_join(&clusters[to].functions, &moved, f, g);
+            parent[from] = to;
+            clusters[to].functions = merged;
+            clusters[to].size += clusters[from].size;
+            clusters[to].samples += clusters[from].samples;
+
+            // the merged cluster inherits the links of both
+            for (c, link) in std::mem::take(&mut links[from]) {
+                links[c].remove(&from);
+                if c == to {
+                    continue;
+                }
+                let joined = match links[to].get(&c) {
+                    Some(other) => other.join(&link),
+                    None => link,
+                };
+                links[to].insert(c, joined);
+                links[c].insert(to, joined);
+                heaviest.push((joined.count, Reverse(to.min(c)), Reverse(to.max(c))));
+            }
+        }
+
+        clusters
+    }
+
+    // joins two clusters - each of them either as it is or reversed - such that the given
+    // functions (f of the first and g of the second) are the closest
+    fn closest_join(&self, first: &[usize], second: &[usize], f: usize, g: usize) -> Vec<usize> {
+        let reversed = |x: &[usize]| x.iter().rev().copied().collect::<Vec<usize>>();
+        let candidates = [
+            [first.to_vec(), second.to_vec()].concat(),
+            [first.to_vec(), reversed(second)].concat(),
+            [reversed(first), second.to_vec()].concat(),
+            [reversed(first), reversed(second)].concat(),
+        ];
+
+        // the bytes between the end of f and the start of g
+        let gap = |order: &Vec<usize>| -> usize {
+            let (p, q) = (
+                order.iter().position(|&x| x == f).unwrap(),
+                order.iter().position(|&x| x == g).unwrap(),
+            );
+            order[p + 1..q]
+                .iter()
+                .map(|&x| self.functions[x].size)
+                .sum()
+        };
+
+        candidates.into_iter().min_by_key(gap).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a call graph of the given (name, size) functions and (caller, callee, count) calls
+    fn graph(functions: &[(&str, usize)], calls: &[(&str, &str, usize)]) -> CallGraph {
+        CallGraph::new(
+            functions
+                .iter()
+                .map(|&(name, size)| Function {
+                    name: name.to_string(),
+                    size,
+                    samples: None,
+                })
+                .collect(),
+            calls
+                .iter()
+                .map(|&(caller, callee, count)| Call {
+                    caller: caller.to_string(),
+                    callee: callee.to_string(),
+                    count,
+                })
+                .collect(),
+        )
+    }
+
+    fn names(order: &[&str]) -> Vec<String> {
+        order.iter().map(|x| x.to_string()).collect()
+    }
+
+    // TEST: main calls a hot and a big cold helper, the hot helper calls a leaf, and another
+    // big function in between is never called
+    fn example() -> CallGraph {
+        let mut graph = graph(
+            &[
+                ("main", 64),
+                ("cold", 4000),
+                ("big", 4096),
+                ("hot", 128),
+                ("leaf", 32),
+            ],
+            &[
+                ("main", "hot", 1000),
+                ("hot", "leaf", 5000),
+                ("main", "cold", 1),
+                ("main", "main", 7),
+                ("main", "unknown", 100),
+            ],
+        );
+        graph.functions[0].samples = Some(1);
+        graph
+    }
+
+    #[test]
+    fn c3_order() {
+        let graph = example();
+        let order = graph.order(FunctionOrder::C3);
+
+        // leaf is appended to hot, hot to main: cold would make their cluster too sparse
+        assert_eq!(order, names(&["main", "hot", "leaf", "cold", "big"]));
+
+        let original = names(&["main", "cold", "big", "hot", "leaf"]);
+        assert!(graph.call_distance(&order) < graph.call_distance(&original));
+        assert_eq!(
+            graph.call_distance(&order),
+            1000 * 64 + 5000 * 128 + (64 + 128 + 32)
+        );
+        assert_eq!(graph.hot_pages(&order, 4096), 2);
+        assert_eq!(graph.hot_pages(&original, 4096), 3);
+    }
+
+    #[test]
+    fn pettis_hansen_order() {
+        let graph = example();
+        let order = graph.order(FunctionOrder::PettisHansen);
+
+        // hot and leaf are merged first, then main is put next to hot, and finally the cluster
+        // is reversed to put main next to cold
+        assert_eq!(order, names(&["leaf", "hot", "main", "cold", "big"]));
+        assert_eq!(graph.call_distance(&order), 1000 * 128 + 5000 * 32 + 64);
+        assert_eq!(graph.hot_pages(&order, 4096), 2);
+    }
+
+    #[test]
+    fn call_graph_from_binary() {
+        let binary = Binary::from_code(0x1000, vec![0xc3; 0x30])
+            .with_symbol("main", 0x1000, 0x10)
+            .with_symbol("f", 0x1010, 0x20);
+        let calls = vec![Call {
+            caller: "main".to_string(),
+            callee: "f".to_string(),
+            count: 2,
+        }];
+        let graph = CallGraph::from_binary(&binary, calls);
+
+        assert_eq!(graph.functions[1].size, 0x20);
+        assert_eq!(graph.call_distance(&names(&["f", "main"])), 2 * 0x20);
+    }
+
+    #[test]
+    fn call_graph_from_yaml() {
+        let yaml = "
+functions:
+  - { name: main, size: 16 }
+  - { name: f, size: 16, samples: 3 }
+calls:
+  - { caller: main, callee: f, count: 2 }
+";
+        let graph: CallGraph = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(graph.functions[1].samples, Some(3));
+        assert_eq!(graph.order(FunctionOrder::C3), names(&["main", "f"]));
+        assert_eq!(graph.call_distance(&names(&["f", "main"])), 2 * 16);
+    }
+}
@@ -111,12 +111,55 @@ impl BasicBlock {
 
     // BasicBlock -> address of the last byte
     // maybe: address of the next instruction ??
-    fn end_address(&self) -> u64 {
+    pub fn end_address(&self) -> u64 {
         let instr: Instruction = *(self.instructions).iter().last().unwrap();
         instr.next_ip() - 1
         // instr.ip() + (instr.len() as u64)
     }
 
+    // BasicBlock -> size of the closing direct branch instruction in bytes (if there is one)
+    pub fn branch_size(&self) -> Option<usize> {
+        let instr: &Instruction = self.instructions.last()?;
+        match instr.flow_control() {
+            FlowControl::ConditionalBranch | FlowControl::UnconditionalBranch => Some(instr.len()),
+            _ => None,
+        }
+    }
+
+    // BasicBlock -> the address where the execution continues without jumping (if it can)
+    // note: a block is either closed by a conditional branch or it was cut at that address
+    pub fn fallthrough(&self) -> Option<u64> {
+        let instr: &Instruction = self.instructions.last()?;
+        match instr.flow_control() {
+            FlowControl::ConditionalBranch | FlowControl::Next | FlowControl::Call => {
+                Some(instr.next_ip())
+            }
+            _ => None,
+        }
+    }
+
+    // BasicBlock -> the target of the closing direct unconditional jump (if there is one)
+    pub fn jump_target(&self) -> Option<u64> {
+        let instr: &Instruction = self.instructions.last()?;
+        match instr.flow_control() {
+            FlowControl::UnconditionalBranch if instr.is_jmp_short_or_near() => {
+                Some(instr.near_branch_target())
+            }
+            _ => None,
+        }
+    }
+
+    // BasicBlock -> the targets where the execution can really continue
+    // note: the targets of an unconditional jump contain the next instruction's address too
+    pub fn successors(&self) -> Vec<u64> {
+        match self.instructions.last().map(|x| x.flow_control()) {
+            Some(FlowControl::UnconditionalBranch) => {
+                self.targets.iter().skip(1).copied().collect()
+            }
+            _ => self.targets.clone(),
+        }
+    }
+
     // BasicBlock + va -> address of the next valid instruction (if va = start then itself)
     fn next_valid_instr(&self, va: u64) -> Result<u64, String> {
         // TODO: what if it returns the next basic block's address ??
@@ -277,6 +320,11 @@ impl ControlFlowGraph {
             blocks.insert(bb.address(), bb);
 
             while let Some(target) = targets.pop() {
+                // the block is already explored (e.g. a loop back to its own start)
+                if blocks.contains_key(&target) {
+                    continue;
+                }
+
                 let cut = blocks.range(..target).next_back().map(|(&x, _)| x);
 
                 match cut {
@@ -385,3 +433,25 @@ impl<'a> dot2::GraphWalk<'a> for ControlFlowGraph {
         t
     }
 }
+
+// the machine code of a counting loop followed by a null check (the test fixture of the
+// modules working on real code, see counting_loop_cfg)
+#[cfg(test)]
+pub(crate) const COUNTING_LOOP: [u8; 17] = [
+    0x31, 0xc0, // +0x0: xor eax, eax
+    0xff, 0xc0, // +0x2: inc eax
+    0x83, 0xf8, 0x0a, // +0x4: cmp eax, 10
+    0x7c, 0xf9, // +0x7: jl +0x2
+    0x48, 0x85, 0xff, // +0x9: test rdi, rdi
+    0x74, 0x01, // +0xc: je +0xf
+    0xc3, // +0xe: ret
+    0x0f, 0x0b, // +0xf: ud2
+];
+
+// the control flow graph of COUNTING_LOOP loaded at 0x1000: the blocks are at 0x1000,
+// 0x1002 (the loop), 0x1009 (the null check), 0x100e (ret) and 0x100f (ud2)
+#[cfg(test)]
+pub(crate) fn counting_loop_cfg() -> ControlFlowGraph {
+    let binary = Binary::from_code(0x1000, COUNTING_LOOP.to_vec());
+    ControlFlowGraph::from_address(&binary, 0x1000)
+}
@@ -0,0 +1,328 @@
+use std::io::Write;
+
+use goblin::elf::header::{EI_CLASS, EI_DATA, ELFCLASS64, ELFDATA2LSB, EM_X86_64};
+use goblin::elf::program_header::{PF_R, PF_X, PT_LOAD, PT_NOTE};
+use goblin::elf::Elf;
+
+use crate::cfg::*;
+use crate::rewrite::*;
+
+// the size of the pages the segments are aligned to
+const PAGE_SIZE: u64 = 0x1000;
+// the alignment of the relocated functions in the new segment
+const FUNCTION_ALIGNMENT: u64 = 16;
+// the encoding of jmp rel32: the opcode followed by the displacement
+const JMP_REL32: u8 = 0xe9;
+const JMP_REL32_SIZE: u64 = 5;
+// the padding between the functions: int3
+const PADDING: u8 = 0xcc;
+
+// the fields of a 64 bit program header at their offsets
+const P_TYPE: usize = 0;
+const P_FLAGS: usize = 4;
+const P_OFFSET: usize = 8;
+const P_VADDR: usize = 16;
+const P_PADDR: usize = 24;
+const P_FILESZ: usize = 32;
+const P_MEMSZ: usize = 40;
+const P_ALIGN: usize = 48;
+
+// a loadable segment of the original file
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// Writes re-laid-out functions into a copy of an ELF file: the code is placed in a new
+/// executable segment appended to the file (in place of the PT_NOTE program header, which
+/// is not needed to run it), and the original entries of the functions jump to their new
+/// code. Only 64 bit little-endian x86-64 files are supported.
+///
+#[derive(Debug, Clone)]
+pub struct ElfWriter {
+    bytes: Vec<u8>,
+    segments: Vec<Segment>,
+    // the offset of the program header turned into the new segment
+    header: usize,
+    // the start of the new segment in the file and in the memory
+    offset: u64,
+    base: u64,
+    code: Vec<u8>,
+}
+
+impl ElfWriter {
+    // creates an instance from the bytes of an ELF file
+    pub fn new(bytes: Vec<u8>) -> Result<Self, RewriteError> {
+        let elf = Elf::parse(&bytes).map_err(|err| RewriteError::Elf(err.to_string()))?;
+        let ident = &elf.header.e_ident;
+        if ident[EI_CLASS] != ELFCLASS64
+            || ident[EI_DATA] != ELFDATA2LSB
+            || elf.header.e_machine != EM_X86_64
+        {
+            return Err(RewriteError::Elf(String::from(
+                "not a 64 bit little-endian x86-64 file",
+            )));
+        }
+
+        let note = elf
+            .program_headers
+            .iter()
+            .position(|x| x.p_type == PT_NOTE)
+            .ok_or(RewriteError::Elf(String::from("no PT_NOTE program header")))?;
+        let header = elf.header.e_phoff as usize + note * elf.header.e_phentsize as usize;
+
+        let segments: Vec<Segment> = elf
+            .program_headers
+            .iter()
+            .filter(|x| x.p_type == PT_LOAD)
+            .map(|x| Segment {
+                offset: x.p_offset,
+                vaddr: x.p_vaddr,
+                filesz: x.p_filesz,
+            })
+            .collect();
+
+        // the new segment starts on a page after every other one, both in the file and in
+        // the memory, hence its offset and address are congruent modulo the page size
+        let end = elf
+            .program_headers
+            .iter()
+            .filter(|x| x.p_type == PT_LOAD)
+            .map(|x| x.p_vaddr + x.p_memsz)
+            .max()
+            .unwrap_or(0);
+        let base = end.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let offset = (bytes.len() as u64).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+        Ok(ElfWriter {
+            bytes,
+            segments,
+            header,
+            offset,
+            base,
+            code: Vec::new(),
+        })
+    }
+
+    // the virtual address of the new segment
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    // re-encodes the blocks of the function in the given order (see relocate) at the end of
+    // the new segment, and patches the original entry of the function with a jump to it
+    // note:    the first bytes of the original function are overwritten, hence the branches
+    //          from other functions into the middle of it still have to work
+    pub fn add_function(
+        &mut self,
+        cfg: &ControlFlowGraph,
+        order: &[u64],
+    ) -> Result<RelocatedCode, RewriteError> {
+        let entry = cfg.address();
+        let jump_end = entry + JMP_REL32_SIZE - 1;
+        if !cfg
+            .blocks()
+            .iter()
+            .any(|b| b.address() <= jump_end && jump_end <= b.end_address())
+        {
+            return Err(RewriteError::Elf(format!(
+                "the function at {entry:#x} is too small for a jump"
+            )));
+        }
+        let patch = self.file_offset(entry, JMP_REL32_SIZE)?;
+
+        let start = (self.code.len() as u64).div_ceil(FUNCTION_ALIGNMENT) * FUNCTION_ALIGNMENT;
+        let relocated = relocate(cfg, order, self.base + start)?;
+        let target = *relocated
+            .blocks
+            .get(&entry)
+            .ok_or(RewriteError::Elf(format!(
+                "the entry of the function at {entry:#x} was rewritten"
+            )))?;
+
+        // nothing is written until every check has passed
+        let displacement = target as i64 - (entry + JMP_REL32_SIZE) as i64;
+        let displacement = i32::try_from(displacement).map_err(|_| {
+            RewriteError::Elf(format!("the function at {entry:#x} is too far for a jump"))
+        })?;
+
+        self.code.resize(start as usize, PADDING);
+        self.code.extend(&relocated.code);
+        self.bytes[patch] = JMP_REL32;
+        self.bytes[patch + 1..patch + 5].copy_from_slice(&displacement.to_le_bytes());
+
+        Ok(relocated)
+    }
+
+    // the bytes of the new ELF file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        bytes.resize(self.offset as usize, 0);
+        bytes.extend(&self.code);
+
+        let size = self.code.len() as u64;
+        let mut set = |field: usize, value: &[u8]| {
+            let at = self.header + field;
+            bytes[at..at + value.len()].copy_from_slice(value);
+        };
+        set(P_TYPE, &PT_LOAD.to_le_bytes());
+        set(P_FLAGS, &(PF_R | PF_X).to_le_bytes());
+        set(P_OFFSET, &self.offset.to_le_bytes());
+        set(P_VADDR, &self.base.to_le_bytes());
+        set(P_PADDR, &self.base.to_le_bytes());
+        set(P_FILESZ, &size.to_le_bytes());
+        set(P_MEMSZ, &size.to_le_bytes());
+        set(P_ALIGN, &PAGE_SIZE.to_le_bytes());
+
+        bytes
+    }
+
+    // writes the new ELF file to the given path, which is made executable
+    pub fn write_to(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(&self.to_bytes())?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+        }
+
+        Ok(())
+    }
+
+    // the offset in the file of the given bytes at the virtual address
+    fn file_offset(&self, address: u64, size: u64) -> Result<usize, RewriteError> {
+        self.segments
+            .iter()
+            .find(|x| x.vaddr <= address && address + size <= x.vaddr + x.filesz)
+            .map(|x| (address - x.vaddr + x.offset) as usize)
+            .ok_or(RewriteError::Elf(format!(
+                "the address {address:#x} is not in a loadable segment"
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::Binary;
+
+    // a minimal executable: the ELF header, a PT_LOAD of the whole file at 0x400000 and a
+    // PT_NOTE, followed by the code at 0x4000b0 (which is the entry)
+    fn minimal_elf() -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+        bytes.resize(16, 0);
+        bytes.extend(2u16.to_le_bytes()); // e_type: EXEC
+        bytes.extend(EM_X86_64.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes()); // e_version
+        bytes.extend(0x4000b0u64.to_le_bytes()); // e_entry
+        bytes.extend(64u64.to_le_bytes()); // e_phoff
+        bytes.extend(0u64.to_le_bytes()); // e_shoff
+        bytes.extend(0u32.to_le_bytes()); // e_flags
+        bytes.extend(
+            [64u16, 56, 2, 64, 0, 0]
+                .iter()
+                .flat_map(|x| x.to_le_bytes()),
+        );
+
+        let size = (64 + 2 * 56 + COUNTING_LOOP.len()) as u64;
+        for (p_type, p_flags, p_vaddr, p_filesz) in [
+            (PT_LOAD, PF_R | PF_X, 0x400000u64, size),
+            (PT_NOTE, PF_R, 0, 0),
+        ] {
+            bytes.extend(p_type.to_le_bytes());
+            bytes.extend(p_flags.to_le_bytes());
+            bytes.extend(0u64.to_le_bytes());
+            bytes.extend(p_vaddr.to_le_bytes());
+            bytes.extend(p_vaddr.to_le_bytes());
+            bytes.extend(p_filesz.to_le_bytes());
+            bytes.extend(p_filesz.to_le_bytes());
+            bytes.extend(PAGE_SIZE.to_le_bytes());
+        }
+        bytes.extend(COUNTING_LOOP);
+
+        bytes
+    }
+
+    #[test]
+    fn relocated_function() {
+        let original = minimal_elf();
+        let path = std::env::temp_dir().join(format!("cfg_sort_elf_{}", std::process::id()));
+        std::fs::write(&path, &original).unwrap();
+        let binary = Binary::from_elf(path.to_string_lossy().to_string());
+        let cfg = ControlFlowGraph::from_address(&binary, 0x4000b0);
+
+        let mut writer = ElfWriter::new(original).unwrap();
+        assert_eq!(writer.base(), 0x401000);
+        let order = [0x4000b0, 0x4000b2, 0x4000b9, 0x4000bf, 0x4000be];
+        let relocated = writer.add_function(&cfg, &order).unwrap();
+        assert_eq!(relocated.address, 0x401000);
+        writer.write_to(&path).unwrap();
+
+        // the new segment is loadable and executable
+        let bytes = std::fs::read(&path).unwrap();
+        let elf = Elf::parse(&bytes).unwrap();
+        let segment = &elf.program_headers[1];
+        assert_eq!(
+            (
+                segment.p_type,
+                segment.p_flags,
+                segment.p_offset,
+                segment.p_vaddr
+            ),
+            (PT_LOAD, PF_R | PF_X, 0x1000, 0x401000)
+        );
+        assert_eq!(bytes[0x1000..], relocated.code);
+
+        // the old entry jumps to the new code, which has the same blocks
+        let binary = Binary::from_elf(path.to_string_lossy().to_string());
+        let entry = ControlFlowGraph::from_address(&binary, 0x4000b0);
+        assert_eq!(entry.blocks()[0].successors(), [0x401000]);
+        let moved = ControlFlowGraph::from_address(&binary, 0x401000);
+        assert_eq!(moved.blocks().len(), cfg.blocks().len());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn too_far_for_a_jump() {
+        // the loadable segment takes 4 GiB of memory, hence the new one starts too far away
+        let mut original = minimal_elf();
+        let memsz = 64 + P_MEMSZ;
+        original[memsz..memsz + 8].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+        let path = std::env::temp_dir().join(format!("cfg_sort_far_{}", std::process::id()));
+        std::fs::write(&path, &original).unwrap();
+        let binary = Binary::from_elf(path.to_string_lossy().to_string());
+        let cfg = ControlFlowGraph::from_address(&binary, 0x4000b0);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut writer = ElfWriter::new(original).unwrap();
+        assert!(writer.base() > 0x4000b0 + i32::MAX as u64);
+        let before = writer.to_bytes();
+        let order = [0x4000b0, 0x4000b2, 0x4000b9, 0x4000bf, 0x4000be];
+        assert!(matches!(
+            writer.add_function(&cfg, &order),
+            Err(RewriteError::Elf(_))
+        ));
+        assert_eq!(writer.to_bytes(), before);
+    }
+
+    #[test]
+    fn unsupported_files() {
+        assert!(matches!(
+            ElfWriter::new(vec![0; 64]),
+            Err(RewriteError::Elf(_))
+        ));
+
+        // the function has to be in the file
+        let mut writer = ElfWriter::new(minimal_elf()).unwrap();
+        let cfg = counting_loop_cfg();
+        assert!(matches!(
+            writer.add_function(&cfg, &[0x1000, 0x1002, 0x1009, 0x100e, 0x100f]),
+            Err(RewriteError::Elf(_))
+        ));
+    }
+}
@@ -0,0 +1,456 @@
+use iced_x86::*;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::cfg::*;
+
+// the probabilities that the heuristics below assign to the edge they predict to be taken
+// (Ball and Larus, Branch prediction for free; Wu and Larus, Static branch frequency and
+// program profile analysis)
+const LOOP_BRANCH: f64 = 0.88;
+const LOOP_EXIT: f64 = 0.80;
+const POINTER: f64 = 0.60;
+const OPCODE: f64 = 0.84;
+const CALL: f64 = 0.78;
+const RETURN: f64 = 0.72;
+const NORETURN: f64 = 0.95;
+
+// the largest probability of getting back to the loop header we believe in
+// note: otherwise the frequency of an infinite loop would be infinite
+const MAX_CYCLIC_PROBABILITY: f64 = 0.999;
+
+// the block frequencies are scaled by this to get integer execution counts,
+// that is: the counts are per this many calls of the function
+pub const FREQUENCY_SCALE: f64 = 1000.0;
+
+// the estimated probabilities of the edges and frequencies of the blocks of a control flow
+// graph - the static replacement of a profile
+#[derive(Debug, Clone)]
+pub struct StaticProfile {
+    // the probability that the edge is taken once its source is executed
+    probabilities: BTreeMap<(u64, u64), f64>,
+    // the expected number of executions of the block per call of the function
+    frequencies: BTreeMap<u64, f64>,
+}
+
+// the information about a CFG the estimation needs
+struct Estimation<'a> {
+    cfg: &'a ControlFlowGraph,
+    // the blocks by their addresses
+    blocks: BTreeMap<u64, &'a BasicBlock>,
+    // the successors of the blocks (only the ones inside the CFG)
+    successors: BTreeMap<u64, Vec<u64>>,
+    // the retreating edges of a depth first search from the entry
+    back_edges: HashSet<(u64, u64)>,
+    // the loops (by their headers) with their bodies, innermost first
+    loops: Vec<(u64, BTreeSet<u64>)>,
+}
+
+impl<'a> Estimation<'a> {
+    fn new(cfg: &'a ControlFlowGraph) -> Self {
+        let blocks: BTreeMap<u64, &BasicBlock> =
+            cfg.blocks().iter().map(|b| (b.address(), b)).collect();
+
+        let mut successors: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (&address, block) in &blocks {
+            let mut targets: Vec<u64> = block
+                .successors()
+                .into_iter()
+                .filter(|x| blocks.contains_key(x))
+                .collect();
+            targets.dedup();
+            successors.insert(address, targets);
+        }
+
+        let mut estimation = Estimation {
+            cfg,
+            blocks,
+            successors,
+            back_edges: HashSet::new(),
+            loops: Vec::new(),
+        };
+        estimation.find_loops();
+
+        estimation
+    }
+
+    // collects the retreating edges by an iterative depth first search, then the natural
+    // loop of every header: the blocks from which a back edge can be reached without
+    // going through the header
+    fn find_loops(&mut self) {
+        let entry = self.cfg.address();
+        let mut on_stack: HashSet<u64> = HashSet::from([entry]);
+        let mut visited: HashSet<u64> = HashSet::from([entry]);
+        let mut stack: Vec<(u64, usize)> = vec![(entry, 0)];
+
+        while let Some((block, i)) = stack.pop() {
+            match self.successors[&block].get(i).copied() {
+                Some(target) => {
+                    stack.push((block, i + 1));
+                    if on_stack.contains(&target) {
+                        self.back_edges.insert((block, target));
+                    } else if visited.insert(target) {
+                        on_stack.insert(target);
+                        stack.push((target, 0));
+                    }
+                }
+                None => {
+                    on_stack.remove(&block);
+                }
+            }
+        }
+
+        let mut predecessors: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (&block, targets) in &self.successors {
+            for &target in targets {
+                predecessors.entry(target).or_default().push(block);
+            }
+        }
+
+        let mut loops: BTreeMap<u64, BTreeSet<u64>> = BTreeMap::new();
+        for &(latch, header) in &self.back_edges {
+            let body = loops.entry(header).or_insert(BTreeSet::from([header]));
+            let mut worklist: Vec<u64> = vec![latch];
+            while let Some(block) = worklist.pop() {
+                if body.insert(block) {
+                    worklist.extend(predecessors.get(&block).into_iter().flatten());
+                }
+            }
+        }
+
+        self.loops = loops.into_iter().collect();
+        self.loops
+            .sort_by_key(|(header, body)| (body.len(), *header));
+    }
+
+    // the body of the innermost loop containing the given block (if there is one)
+    fn innermost_loop(&self, block: u64) -> Option<&BTreeSet<u64>> {
+        self.loops
+            .iter()
+            .map(|(_, body)| body)
+            .find(|body| body.contains(&block))
+    }
+
+    // the probability of the edges leaving the given block
+    fn probabilities(&self, block: u64) -> Vec<(u64, f64)> {
+        let targets = &self.successors[&block];
+        let instr = self.blocks[&block].instructions().last().unwrap();
+
+        match targets[..] {
+            [] => Vec::new(),
+            [target] => vec![(target, 1.0)],
+            _ if instr.flow_control() == FlowControl::ConditionalBranch => {
+                let taken = instr.near_branch_target();
+                let fallthrough = instr.next_ip();
+                if !(targets.contains(&taken) && targets.contains(&fallthrough)) {
+                    return targets
+                        .iter()
+                        .map(|&x| (x, 1.0 / targets.len() as f64))
+                        .collect();
+                }
+
+                let p = self.taken_probability(block, taken, fallthrough);
+                vec![(taken, p), (fallthrough, 1.0 - p)]
+            }
+            _ => targets
+                .iter()
+                .map(|&x| (x, 1.0 / targets.len() as f64))
+                .collect(),
+        }
+    }
+
+    // the probability that the conditional branch closing the block is taken: the predictions
+    // of all the applicable heuristics are combined by the Dempster-Shafer rule
+    fn taken_probability(&self, block: u64, taken: u64, fallthrough: u64) -> f64 {
+        let mut predictions: Vec<f64> = Vec::new();
+
+        // loop branch heuristic: the back edge is taken
+        let back = |target: u64| self.back_edges.contains(&(block, target));
+        if back(taken) != back(fallthrough) {
+            predictions.push(Self::towards(back(taken), LOOP_BRANCH));
+        }
+
+        // loop exit heuristic: the edge leaving the loop is not taken
+        if let Some(body) = self.innermost_loop(block) {
+            let exits = |target: u64| !body.contains(&target);
+            if exits(taken) != exits(fallthrough) {
+                predictions.push(Self::towards(exits(fallthrough), LOOP_EXIT));
+            }
+        }
+
+        // pointer and opcode heuristics: they depend on the comparison before the branch
+        predictions.extend(self.comparison_prediction(block));
+
+        // call heuristic: the successor making a call is not taken
+        let calls = |target: u64| {
+            self.blocks[&target]
+                .instructions()
+                .iter()
+                .any(|x| x.flow_control() == FlowControl::Call)
+        };
+        if calls(taken) != calls(fallthrough) {
+            predictions.push(Self::towards(calls(fallthrough), CALL));
+        }
+
+        // return heuristic: the successor returning is not taken
+        let returns = |target: u64| {
+            self.blocks[&target]
+                .instructions()
+                .last()
+                .unwrap()
+                .flow_control()
+                == FlowControl::Return
+        };
+        if returns(taken) != returns(fallthrough) {
+            predictions.push(Self::towards(returns(fallthrough), RETURN));
+        }
+
+        // noreturn heuristic: the successor that never gets anywhere (e.g. ud2 or int3
+        // placed after a call to a noreturn function, or hlt) is not taken
+        let noreturn = |target: u64| {
+            let last = self.blocks[&target].instructions().last().unwrap();
+            matches!(
+                last.flow_control(),
+                FlowControl::Exception | FlowControl::Interrupt
+            ) || last.mnemonic() == Mnemonic::Hlt
+        };
+        if noreturn(taken) != noreturn(fallthrough) {
+            predictions.push(Self::towards(noreturn(fallthrough), NORETURN));
+        }
+
+        predictions
+            .into_iter()
+            .fold(0.5, |p, q| p * q / (p * q + (1.0 - p) * (1.0 - q)))
+    }
+
+    // the probability of taking the branch if the heuristic predicts the taken edge (or not)
+    fn towards(taken: bool, probability: f64) -> f64 {
+        match taken {
+            true => probability,
+            false => 1.0 - probability,
+        }
+    }
+
+    // the prediction of the pointer heuristic (comparing 64 bit registers for equality is
+    // likely to fail) or the opcode heuristic (an integer is not negative and it is not
+    // equal to a constant) using the comparison right before the branch
+    fn comparison_prediction(&self, block: u64) -> Option<f64> {
+        let instructions = self.blocks[&block].instructions();
+        let branch = instructions.last()?;
+        let compare = instructions.iter().rev().nth(1)?;
+
+        let register = |i: u32| match compare.op_kind(i) {
+            OpKind::Register => Some(compare.op_register(i)),
+            _ => None,
+        };
+        let immediate = (compare.op_count() == 2)
+            .then(|| compare.try_immediate(1).ok())
+            .flatten();
+        let (zero, pointer) = match compare.mnemonic() {
+            Mnemonic::Test => (
+                register(0).is_some() && register(0) == register(1),
+                register(0).is_some_and(|r| r.size() == 8),
+            ),
+            Mnemonic::Cmp => (
+                immediate == Some(0),
+                register(0).is_some_and(|r| r.size() == 8)
+                    && (register(1).is_some_and(|r| r.size() == 8) || immediate == Some(0)),
+            ),
+            _ => return None,
+        };
+
+        let equal = match branch.condition_code() {
+            ConditionCode::e => true,
+            ConditionCode::ne => false,
+            // x < 0 and x <= 0 fail
+            ConditionCode::s | ConditionCode::l | ConditionCode::le if zero => {
+                return Some(1.0 - OPCODE)
+            }
+            ConditionCode::ns | ConditionCode::ge | ConditionCode::g if zero => {
+                return Some(OPCODE)
+            }
+            _ => return None,
+        };
+
+        match (pointer, zero || immediate.is_none()) {
+            (true, _) => Some(Self::towards(!equal, POINTER)),
+            // x == constant fails
+            (false, false) => Some(Self::towards(!equal, OPCODE)),
+            (false, true) => None,
+        }
+    }
+
+    // the frequencies of the blocks of the given region relative to its head (Wu and Larus):
+    // the blocks are visited in topological order (without the back edges), the frequency
+    // of a block is the sum of its incoming edges' frequencies, divided by 1 - the cyclic
+    // probability for inner loop headers - whose back edge probabilities are already known
+    fn propagate(
+        &self,
+        head: u64,
+        body: &BTreeSet<u64>,
+        probabilities: &HashMap<(u64, u64), f64>,
+        back_probabilities: &mut HashMap<(u64, u64), f64>,
+        outermost: bool,
+    ) -> BTreeMap<u64, f64> {
+        let mut indegree: BTreeMap<u64, usize> = body.iter().map(|&x| (x, 0)).collect();
+        for &block in body {
+            for target in &self.successors[&block] {
+                if body.contains(target) && !self.back_edges.contains(&(block, *target)) {
+                    *indegree.get_mut(target).unwrap() += 1;
+                }
+            }
+        }
+
+        let mut frequencies: BTreeMap<u64, f64> = BTreeMap::new();
+        let mut incoming: HashMap<u64, f64> = HashMap::from([(head, 1.0)]);
+        let mut ready: Vec<u64> = vec![head];
+        // the blocks entering the region elsewhere (irreducible loops) get only what flows in
+        ready.extend(
+            indegree
+                .iter()
+                .filter(|(&x, &d)| d == 0 && x != head)
+                .map(|(&x, _)| x),
+        );
+        ready.reverse();
+
+        while let Some(block) = ready.pop() {
+            let mut frequency = incoming.get(&block).copied().unwrap_or(0.0);
+            if block != head || outermost {
+                let cyclic: f64 = back_probabilities
+                    .iter()
+                    .filter(|((_, to), _)| *to == block)
+                    .map(|(_, p)| p)
+                    .sum();
+                frequency /= 1.0 - cyclic.min(MAX_CYCLIC_PROBABILITY);
+            }
+            frequencies.insert(block, frequency);
+
+            for &target in &self.successors[&block] {
+                let edge = frequency * probabilities[&(block, target)];
+                if target == head && !outermost {
+                    back_probabilities.insert((block, target), edge);
+                }
+                if body.contains(&target) && !self.back_edges.contains(&(block, target)) {
+                    *incoming.entry(target).or_insert(0.0) += edge;
+                    let degree = indegree.get_mut(&target).unwrap();
+                    *degree -= 1;
+                    if *degree == 0 {
+                        ready.push(target);
+                    }
+                }
+            }
+        }
+
+        frequencies
+    }
+}
+
+impl StaticProfile {
+    // estimates the edge probabilities with the heuristics and then propagates the block
+    // frequencies over the loop forest: from the innermost loops to the whole function
+    pub fn from_cfg(cfg: &ControlFlowGraph) -> Self {
+        let estimation = Estimation::new(cfg);
+
+        let mut probabilities: HashMap<(u64, u64), f64> = HashMap::new();
+        for &block in estimation.blocks.keys() {
+            for (target, p) in estimation.probabilities(block) {
+                probabilities.insert((block, target), p);
+            }
+        }
+
+        let mut back_probabilities: HashMap<(u64, u64), f64> = HashMap::new();
+        for (header, body) in &estimation.loops {
+            estimation.propagate(
+                *header,
+                body,
+                &probabilities,
+                &mut back_probabilities,
+                false,
+            );
+        }
+
+        let everything: BTreeSet<u64> = estimation.blocks.keys().copied().collect();
+        let frequencies = estimation.propagate(
+            cfg.address(),
+            &everything,
+            &probabilities,
+            &mut back_probabilities,
+            true,
+        );
+
+        StaticProfile {
+            probabilities: probabilities.into_iter().collect(),
+            frequencies,
+        }
+    }
+
+    // the probability that the edge is taken once its source is executed
+    pub fn probability(&self, from: u64, to: u64) -> f64 {
+        self.probabilities.get(&(from, to)).copied().unwrap_or(0.0)
+    }
+
+    // the expected number of executions of the block per call of the function
+    pub fn frequency(&self, block: u64) -> f64 {
+        self.frequencies.get(&block).copied().unwrap_or(0.0)
+    }
+
+    // the execution counts of the blocks per FREQUENCY_SCALE calls of the function
+    pub fn block_counts(&self) -> HashMap<u64, usize> {
+        self.frequencies
+            .iter()
+            .map(|(&block, f)| (block, (f * FREQUENCY_SCALE).round() as usize))
+            .collect()
+    }
+
+    // the number of times the edges are taken per FREQUENCY_SCALE calls of the function
+    pub fn edge_counts(&self) -> HashMap<(u64, u64), usize> {
+        self.probabilities
+            .keys()
+            .map(|&(from, to)| {
+                let count = self.frequency(from) * self.probability(from, to) * FREQUENCY_SCALE;
+                ((from, to), count.round() as usize)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vagraph::vag::VirtualAddressGraph;
+
+    // TEST: a counting loop followed by a null check
+    #[test]
+    fn loop_and_null_check() {
+        let cfg = counting_loop_cfg();
+        let profile = StaticProfile::from_cfg(&cfg);
+
+        // the probabilities of the edges leaving a block add up to 1
+        for block in cfg.blocks() {
+            let total: f64 = block
+                .successors()
+                .iter()
+                .map(|&x| profile.probability(block.address(), x))
+                .sum();
+            assert!(block.successors().is_empty() || (total - 1.0).abs() < 1e-9);
+        }
+
+        // the loop branch and the loop exit heuristics agree: the loop iterates
+        assert!(profile.probability(0x1002, 0x1002) > 0.9);
+        assert!(profile.frequency(0x1002) > 10.0);
+        // what goes into the loop comes out of it
+        assert!((profile.frequency(0x1009) - 1.0).abs() < 1e-9);
+
+        // the null pointer and the ud2 are unlikely, the return is likely
+        assert!(profile.probability(0x1009, 0x100f) < 0.1);
+        assert!(profile.frequency(0x100e) > 0.9);
+        assert_eq!(profile.block_counts()[&0x1000], FREQUENCY_SCALE as usize);
+
+        // the layouts see the estimate as a profile: the likely return falls through
+        let vag = VirtualAddressGraph::from_cfg_estimated(&cfg);
+        assert!(vag.has_profile());
+        let order = vag.pettis_hansen_order();
+        let position = |x: u64| order.iter().position(|&y| y == x).unwrap();
+        assert_eq!(position(0x100e), position(0x1009) + 1);
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::Write;
+
+use crate::binary::Binary;
+
+/// The files that tell a linker the order of the functions.
+///
+/// # Variants
+///
+/// * `SymbolOrdering`  - the symbols one per line, for lld and gold `--symbol-ordering-file`;
+/// * `LinkerScript`    - a GNU ld script placing the `.text.<symbol>` sections in order;
+/// * `Msvc`            - the symbols one per line, for the MSVC linker's `/ORDER:@file`;
+///
+/// The `LinkerScript` needs the code to be compiled with `-ffunction-sections`, the `Msvc`
+/// order needs `/Gy` and the decorated names of the functions.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrderFile {
+    #[default]
+    SymbolOrdering,
+    LinkerScript,
+    Msvc,
+}
+
+/// Writes the given order of the functions in the given format.
+///
+/// # Arguments
+///
+/// * `output`  - where the file is written;
+/// * `symbols` - the names of the functions in order (e.g. the output of CallGraph::order);
+/// * `format`  - the format of the file;
+///
+/// # Errors
+///
+/// The errors of the output.
+///
+pub fn write_order_file<W: Write, S: AsRef<str>>(
+    output: &mut W,
+    symbols: &[S],
+    format: OrderFile,
+) -> std::io::Result<()> {
+    match format {
+        OrderFile::SymbolOrdering | OrderFile::Msvc => {
+            for symbol in symbols {
+                writeln!(output, "{}", symbol.as_ref())?;
+            }
+        }
+        OrderFile::LinkerScript => {
+            // the rest of the code follows the ordered functions as usual, the script is
+            // inserted into the default one of the linker
+            writeln!(output, "SECTIONS")?;
+            writeln!(output, "{{")?;
+            writeln!(output, "  .text :")?;
+            writeln!(output, "  {{")?;
+            for symbol in symbols {
+                writeln!(output, "    *(.text.{})", symbol.as_ref())?;
+            }
+            writeln!(output, "    *(.text .text.*)")?;
+            writeln!(output, "  }}")?;
+            writeln!(output, "}}")?;
+            writeln!(output, "INSERT BEFORE .fini;")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the order of the functions starting at the given virtual addresses in the given
+/// format, where the names of the functions are the symbols of the binary. The addresses
+/// without a symbol are skipped.
+///
+/// # Arguments
+///
+/// * `output`      - where the file is written;
+/// * `binary`      - the binary whose symbols name the functions;
+/// * `addresses`   - the addresses of the functions in order;
+/// * `format`      - the format of the file;
+///
+/// # Errors
+///
+/// The errors of the output.
+///
+pub fn write_address_order_file<W: Write>(
+    output: &mut W,
+    binary: &Binary,
+    addresses: &[u64],
+    format: OrderFile,
+) -> std::io::Result<()> {
+    let symbols: Vec<&str> = addresses
+        .iter()
+        .filter_map(|&address| binary.symbol_at(address))
+        .map(|symbol| symbol.name.as_str())
+        .collect();
+
+    write_order_file(output, &symbols, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(symbols: &[&str], format: OrderFile) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        write_order_file(&mut output, symbols, format).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn order_files() {
+        let symbols = ["main", "_Z3hotv", "leaf"];
+
+        assert_eq!(
+            written(&symbols, OrderFile::SymbolOrdering),
+            "main\n_Z3hotv\nleaf\n"
+        );
+        assert_eq!(written(&symbols, OrderFile::Msvc), "main\n_Z3hotv\nleaf\n");
+        assert_eq!(
+            written(&symbols, OrderFile::LinkerScript),
+            "SECTIONS\n{\n  .text :\n  {\n    *(.text.main)\n    *(.text._Z3hotv)\n    \
+             *(.text.leaf)\n    *(.text .text.*)\n  }\n}\nINSERT BEFORE .fini;\n"
+        );
+    }
+
+    #[test]
+    fn order_file_from_addresses() {
+        let binary = Binary::from_code(0x1000, vec![0xc3; 0x30])
+            .with_symbol("leaf", 0x1020, 0x10)
+            .with_symbol("main", 0x1000, 0x20);
+        assert_eq!(binary.symbols()[0].name, "main");
+        assert_eq!(binary.symbol("leaf").map(|x| x.address), Some(0x1020));
+
+        // the addresses inside a function name it too, the unknown ones are skipped
+        let mut output: Vec<u8> = Vec::new();
+        write_address_order_file(
+            &mut output,
+            &binary,
+            &[0x1020, 0x1040, 0x1004],
+            OrderFile::SymbolOrdering,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "leaf\nmain\n");
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::BufRead;
+
+use crate::cfg::*;
+use crate::profile::*;
+
+// reads the next non-empty line of the profile with its number
+fn next_line<R: BufRead>(
+    lines: &mut std::iter::Enumerate<std::io::Lines<R>>,
+) -> Result<Option<(usize, String)>, ProfileError> {
+    for (number, line) in lines.by_ref() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            return Ok(Some((number + 1, line.trim().to_string())));
+        }
+    }
+    Ok(None)
+}
+
+// parses a hex address with an optional 0x prefix
+fn address(field: &str) -> Result<u64, String> {
+    let hex = field.strip_prefix("0x").unwrap_or(field);
+    u64::from_str_radix(hex, 16).map_err(|_| format!("invalid address: {field:?}"))
+}
+
+// parses an entry of a section: <first><separator><second>:<count>
+fn entry(line: &str, separator: &str) -> Result<(u64, u64, usize), String> {
+    let (pair, count) = line
+        .rsplit_once(':')
+        .ok_or_else(|| format!("missing count: {line:?}"))?;
+    let (first, second) = pair
+        .split_once(separator)
+        .ok_or_else(|| format!("missing {separator:?}: {line:?}"))?;
+    let count: usize = count
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid count: {count:?}"))?;
+
+    Ok((address(first.trim())?, address(second.trim())?, count))
+}
+
+// reads an AutoFDO text profile (the input of create_llvm_prof --profiler=text) and counts how
+// many times the edges of the control flow graph were taken and its blocks were executed
+// the profile has two sections, each one starts with the number of its entries
+//      <begin>-<end>:<count>   the code from begin to end was executed without a taken branch
+//      <from>-><to>:<count>    the branch from from to to was taken
+// note:    the addresses are in hex, the bias (where the binary was loaded minus its virtual
+//          address) is subtracted from them - the entries outside of the graph are ignored
+// note:    anything after the two sections (e.g. address counts) is not needed
+pub fn parse_text<R: BufRead>(
+    reader: R,
+    cfg: &ControlFlowGraph,
+    bias: u64,
+) -> Result<EdgeProfile, ProfileError> {
+    let blocks = BlockMap::new(cfg);
+    let mut profile = EdgeProfile::default();
+    let mut lines = reader.lines().enumerate();
+
+    for separator in ["-", "->"] {
+        let Some((number, header)) = next_line(&mut lines)? else {
+            break;
+        };
+        let entries: usize = header.parse().map_err(|_| ProfileError::Parse {
+            line: number,
+            message: format!("invalid number of entries: {header:?}"),
+        })?;
+
+        for _ in 0..entries {
+            let Some((number, line)) = next_line(&mut lines)? else {
+                break;
+            };
+            let (first, second, count) =
+                entry(&line, separator).map_err(|message| ProfileError::Parse {
+                    line: number,
+                    message,
+                })?;
+            let (first, second) = (first.wrapping_sub(bias), second.wrapping_sub(bias));
+
+            // a range: the blocks on the fall-through path
+            if separator == "-" {
+                let Some(path) = blocks.fallthrough_path(first, second) else {
+                    continue;
+                };
+                for block in &path {
+                    profile.add_block(block.address(), count);
+                }
+                for step in path.windows(2) {
+                    profile.add_edge(step[0].address(), step[1].address(), count);
+                }
+                continue;
+            }
+
+            // a taken branch
+            let (Some(source), Some(target)) = (blocks.containing(first), blocks.starting(second))
+            else {
+                continue;
+            };
+            if source.successors().contains(&target.address()) {
+                profile.add_edge(source.address(), target.address(), count);
+            }
+        }
+    }
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vagraph::vag::*;
+
+    // TEST: the profile of a counting loop followed by a null check
+    #[test]
+    fn text_fixture() {
+        let cfg = counting_loop_cfg();
+
+        let text = std::fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/loop.afdo.txt"
+        ))
+        .unwrap();
+        let profile = parse_text(text.as_bytes(), &cfg, 0).unwrap();
+
+        assert_eq!(profile.block_count(0x1002), 100);
+        assert_eq!(profile.block_count(0x1009), 10);
+        assert_eq!(profile.block_count(0x100e), 9);
+        assert_eq!(profile.edge_count(0x1002, 0x1002), 90);
+        assert_eq!(profile.edge_count(0x1002, 0x1009), 10);
+        assert_eq!(profile.edge_count(0x1009, 0x100e), 9);
+        assert_eq!(profile.edge_count(0x1009, 0x100f), 1);
+
+        // the counts are carried into the VAG
+        let vag = VirtualAddressGraph::from_cfg_autofdo(&cfg, text.as_bytes(), 0).unwrap();
+        assert!(vag.has_profile());
+        assert_eq!(
+            vag.node_at_target(Vertex::Id(0x1002))
+                .edge_weight(Vertex::Id(0x1002)),
+            Some(90)
+        );
+
+        // a malformed entry is reported with its line
+        let broken = "1\n1002-1007:90\n1\n1007-1002:90\n";
+        match parse_text(broken.as_bytes(), &cfg, 0) {
+            Err(ProfileError::Parse { line, .. }) => assert_eq!(line, 4),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::BufRead;
+
+use crate::cfg::*;
+use crate::profile::*;
+
+// a location of a BOLT profile: <kind> <symbol> <offset>, where the offset (in hex) is
+// relative to the start of the symbol
+// note: the kind tells whether the symbol is a function or some memory, it is not needed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location<'a> {
+    symbol: &'a str,
+    offset: u64,
+}
+
+impl<'a> Location<'a> {
+    fn parse(fields: &[&'a str]) -> Result<Self, String> {
+        let [_, symbol, offset] = fields else {
+            return Err(format!("invalid location: {:?}", fields.join(" ")));
+        };
+        let offset =
+            u64::from_str_radix(offset, 16).map_err(|_| format!("invalid offset: {offset:?}"))?;
+
+        Ok(Location { symbol, offset })
+    }
+
+    // the address of the location in the function (if it is in the function)
+    fn address(&self, function: &str, start: u64) -> Option<u64> {
+        (self.symbol == function).then_some(start + self.offset)
+    }
+}
+
+// reads a BOLT profile (the .fdata output of perf2bolt) and counts how many times the edges
+// of the control flow graph of the given function were taken and its blocks were executed
+// the lines of a profile are either
+//      <from kind> <from symbol> <from offset> <to kind> <to symbol> <to offset> <mispredictions> <count>
+// for the taken branches, or (after a `no_lbr` header) samples without branch records
+//      <kind> <symbol> <offset> <count>
+// note:    the graph is assumed to start at the function's symbol: a branch from another
+//          function to its start is a call, the number of calls is the entry block's count
+// note:    the fall-through edges are not in the profile, they are inferred from the taken
+//          branches (see EdgeProfile::infer_fallthroughs) - the samples only count blocks
+pub fn parse_fdata<R: BufRead>(
+    reader: R,
+    cfg: &ControlFlowGraph,
+    function: &str,
+) -> Result<EdgeProfile, ProfileError> {
+    let blocks = BlockMap::new(cfg);
+    let start = cfg.address();
+    let mut profile = EdgeProfile::default();
+    let mut no_lbr = false;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let error = |message: String| ProfileError::Parse {
+            line: number + 1,
+            message,
+        };
+
+        // the headers of the profile (the event names after no_lbr are not needed)
+        match fields.first() {
+            None => continue,
+            Some(&"boltedcollection") => continue,
+            Some(&"no_lbr") => {
+                no_lbr = true;
+                continue;
+            }
+            _ => (),
+        }
+
+        let (count, locations) = match (no_lbr, fields.len()) {
+            (false, 8) => (fields[7], &fields[..6]),
+            (true, 4) => (fields[3], &fields[..3]),
+            _ => {
+                return Err(error(format!(
+                    "unexpected number of fields: {}",
+                    fields.len()
+                )))
+            }
+        };
+        let count: usize = count
+            .parse()
+            .map_err(|_| error(format!("invalid count: {count:?}")))?;
+
+        // a sample: the instruction's block was executed
+        if no_lbr {
+            let location = Location::parse(locations).map_err(error)?;
+            if let Some(block) = location
+                .address(function, start)
+                .and_then(|x| blocks.containing(x))
+            {
+                profile.add_block(block.address(), count);
+            }
+            continue;
+        }
+
+        // a taken branch: either inside the function or a call to it
+        let from = Location::parse(&locations[..3]).map_err(error)?;
+        let to = Location::parse(&locations[3..]).map_err(error)?;
+        match (from.address(function, start), to.address(function, start)) {
+            (Some(from), Some(to)) => {
+                let (Some(source), Some(target)) = (blocks.containing(from), blocks.starting(to))
+                else {
+                    continue;
+                };
+                if source.successors().contains(&target.address()) {
+                    profile.add_edge(source.address(), target.address(), count);
+                }
+            }
+            (None, Some(to)) if to == start => profile.add_block(start, count),
+            _ => continue,
+        }
+    }
+
+    if !no_lbr {
+        profile.infer_fallthroughs(cfg);
+    }
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vagraph::vag::*;
+
+    // TEST: the profile of a counting loop followed by a null check
+    #[test]
+    fn fdata_fixture() {
+        let cfg = counting_loop_cfg();
+
+        // the function is called 10 times, the loop runs 10 times per call
+        let fdata =
+            std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/loop.fdata"))
+                .unwrap();
+        let profile = parse_fdata(fdata.as_bytes(), &cfg, "count").unwrap();
+
+        assert_eq!(profile.block_count(0x1000), 10);
+        assert_eq!(profile.edge_count(0x1000, 0x1002), 10);
+        assert_eq!(profile.edge_count(0x1002, 0x1002), 90);
+        assert_eq!(profile.block_count(0x1002), 100);
+        assert_eq!(profile.edge_count(0x1002, 0x1009), 10);
+        assert_eq!(profile.edge_count(0x1009, 0x100e), 9);
+        assert_eq!(profile.edge_count(0x1009, 0x100f), 1);
+
+        // the counts are carried into the VAG
+        let vag = VirtualAddressGraph::from_cfg_fdata(&cfg, fdata.as_bytes(), "count").unwrap();
+        assert!(vag.has_profile());
+        assert_eq!(
+            vag.node_at_target(Vertex::Id(0x1009))
+                .edge_weight(Vertex::Id(0x100e)),
+            Some(9)
+        );
+
+        // without branch records only the blocks are counted
+        let samples = "no_lbr cycles:u\n1 count 4 60\n1 count 8 40\n1 count e 5\n";
+        let profile = parse_fdata(samples.as_bytes(), &cfg, "count").unwrap();
+        assert_eq!(profile.block_count(0x1002), 100);
+        assert_eq!(profile.block_count(0x100e), 5);
+
+        // a malformed entry is reported with its line
+        let broken = "1 count 7 1 count 2 0 90\n1 count 7 1 count\n";
+        match parse_fdata(broken.as_bytes(), &cfg, "count") {
+            Err(ProfileError::Parse { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::cfg::*;
+
+pub mod autofdo;
+pub mod fdata;
+pub mod perf;
+
+// the measured behaviour of a control flow graph: how many times the edges were taken and
+// how many times the blocks were executed in the samples
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EdgeProfile {
+    edges: BTreeMap<(u64, u64), usize>,
+    blocks: BTreeMap<u64, usize>,
+}
+
+impl EdgeProfile {
+    // the number of times the edge was taken
+    pub fn edge_count(&self, from: u64, to: u64) -> usize {
+        self.edges.get(&(from, to)).copied().unwrap_or(0)
+    }
+
+    // the number of times the block was executed
+    pub fn block_count(&self, block: u64) -> usize {
+        self.blocks.get(&block).copied().unwrap_or(0)
+    }
+
+    // the number of times the edges were taken (only the ones seen)
+    pub fn edge_counts(&self) -> HashMap<(u64, u64), usize> {
+        self.edges
+            .iter()
+            .map(|(&edge, &count)| (edge, count))
+            .collect()
+    }
+
+    // the number of times the blocks were executed (only the ones seen)
+    pub fn block_counts(&self) -> HashMap<u64, usize> {
+        self.blocks
+            .iter()
+            .map(|(&block, &count)| (block, count))
+            .collect()
+    }
+
+    // whether nothing was recorded for the graph
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty() && self.blocks.is_empty()
+    }
+
+    // the blocks are executed as many times as they are entered and the rest of the executions
+    // of a block falls through: with only the taken branches known (and maybe some block
+    // counts) the fall-through edges are inferred in the order of the addresses
+    // note: a block is executed at least as many times as it is left by a taken branch
+    fn infer_fallthroughs(&mut self, cfg: &ControlFlowGraph) {
+        for block in cfg.blocks() {
+            let address = block.address();
+            let fallthrough = block
+                .fallthrough()
+                .filter(|x| block.successors().contains(x));
+
+            let incoming: usize = self
+                .edges
+                .iter()
+                .filter(|((_, to), _)| *to == address)
+                .map(|(_, count)| count)
+                .sum();
+            let taken: usize = self
+                .edges
+                .iter()
+                .filter(|((from, to), _)| *from == address && Some(*to) != fallthrough)
+                .map(|(_, count)| count)
+                .sum();
+
+            let count = incoming.max(taken).max(self.block_count(address));
+            self.blocks.insert(address, count);
+            if let Some(fallthrough) = fallthrough {
+                self.edges.insert((address, fallthrough), count - taken);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, from: u64, to: u64, count: usize) {
+        *self.edges.entry((from, to)).or_insert(0) += count;
+    }
+
+    fn add_block(&mut self, block: u64, count: usize) {
+        *self.blocks.entry(block).or_insert(0) += count;
+    }
+}
+
+// the blocks of a control flow graph by address: finds the block an address belongs to
+struct BlockMap<'a> {
+    blocks: BTreeMap<u64, &'a BasicBlock>,
+}
+
+impl<'a> BlockMap<'a> {
+    fn new(cfg: &'a ControlFlowGraph) -> Self {
+        BlockMap {
+            blocks: cfg.blocks().iter().map(|b| (b.address(), b)).collect(),
+        }
+    }
+
+    // the block containing the given address (if there is one)
+    fn containing(&self, address: u64) -> Option<&'a BasicBlock> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, &block)| block)
+            .filter(|block| address <= block.end_address())
+    }
+
+    // the block starting at the given address (if there is one)
+    fn starting(&self, address: u64) -> Option<&'a BasicBlock> {
+        self.blocks.get(&address).copied()
+    }
+
+    // the blocks executed one after the other without a taken branch from the block starting
+    // at start to the block containing end - None if there is no such path in the graph
+    fn fallthrough_path(&self, start: u64, end: u64) -> Option<Vec<&'a BasicBlock>> {
+        let mut block = self.starting(start)?;
+        let last = self.containing(end)?;
+        let mut path: Vec<&BasicBlock> = vec![block];
+
+        while block.address() != last.address() {
+            let next = self.starting(block.end_address() + 1)?;
+            if next.address() > last.address() || block.fallthrough() != Some(next.address()) {
+                return None;
+            }
+            path.push(next);
+            block = next;
+        }
+
+        Some(path)
+    }
+}
+
+/// The errors that can arise while reading a profile.
+///
+/// # Variants
+///
+/// * `Io`      - the profile could not be read;
+/// * `Parse`   - the given line of the profile is malformed;
+///
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Cannot read profile: {err}"),
+            Self::Parse { line, message } => {
+                write!(f, "Cannot parse profile at line {line}: {message}")
+            }
+        }
+    }
+}
+
+impl Error for ProfileError {}
+
+impl From<std::io::Error> for ProfileError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
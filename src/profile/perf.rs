@@ -0,0 +1,136 @@
+use std::io::BufRead;
+
+use crate::cfg::*;
+use crate::profile::*;
+
+// a taken branch recorded by the last branch record (LBR) hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Branch {
+    from: u64,
+    to: u64,
+}
+
+impl Branch {
+    // parses an entry of the brstack field: from/to/flags/in_tx/abort/cycles
+    // note: only the addresses are needed, the rest of the fields are optional
+    fn parse(entry: &str) -> Result<Self, String> {
+        let mut fields = entry.split('/');
+        let mut address = || -> Result<u64, String> {
+            let field = fields.next().unwrap_or_default();
+            let hex = field.strip_prefix("0x").unwrap_or(field);
+            u64::from_str_radix(hex, 16).map_err(|_| format!("invalid address: {field:?}"))
+        };
+
+        Ok(Branch {
+            from: address()?,
+            to: address()?,
+        })
+    }
+}
+
+// reads the output of `perf script -F brstack` (of a `perf record -b` session) and counts how
+// many times the edges of the control flow graph were taken and its blocks were executed
+// note:    the addresses in the samples are runtime addresses, the bias (where the binary was
+//          loaded minus its virtual address) is subtracted from them - the branches outside
+//          of the graph are ignored
+// note:    a sample lists the most recent branch first, hence between the target of a
+//          branch and the source of the one before it in the list the blocks are executed
+//          one after the other: these are the fall-through edges
+pub fn parse_brstack<R: BufRead>(
+    reader: R,
+    cfg: &ControlFlowGraph,
+    bias: u64,
+) -> Result<EdgeProfile, ProfileError> {
+    let blocks = BlockMap::new(cfg);
+    let mut profile = EdgeProfile::default();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        // the other fields of perf script (e.g. the command or the pid) have no slashes
+        let mut sample: Vec<Branch> = Vec::new();
+        for entry in line.split_whitespace().filter(|x| x.contains('/')) {
+            let branch = Branch::parse(entry).map_err(|message| ProfileError::Parse {
+                line: number + 1,
+                message,
+            })?;
+            sample.push(Branch {
+                from: branch.from.wrapping_sub(bias),
+                to: branch.to.wrapping_sub(bias),
+            });
+        }
+
+        // the taken branches
+        for branch in &sample {
+            let (Some(source), Some(target)) =
+                (blocks.containing(branch.from), blocks.starting(branch.to))
+            else {
+                continue;
+            };
+            if source.successors().contains(&target.address()) {
+                profile.add_edge(source.address(), target.address(), 1);
+            }
+        }
+
+        // the fall-through paths between two consecutive branches
+        for pair in sample.windows(2) {
+            let (newer, older) = (pair[0], pair[1]);
+            let Some(path) = blocks.fallthrough_path(older.to, newer.from) else {
+                continue;
+            };
+
+            for block in &path {
+                profile.add_block(block.address(), 1);
+            }
+            for step in path.windows(2) {
+                profile.add_edge(step[0].address(), step[1].address(), 1);
+            }
+        }
+    }
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vagraph::vag::*;
+
+    // TEST: samples of a counting loop followed by a null check
+    #[test]
+    fn brstack_fixture() {
+        let cfg = counting_loop_cfg();
+
+        // the binary was loaded at 0x555555555000
+        let brstack = std::fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/perf_brstack.txt"
+        ))
+        .unwrap();
+        let profile = parse_brstack(brstack.as_bytes(), &cfg, 0x555555554000).unwrap();
+
+        assert_eq!(profile.edge_count(0x1002, 0x1002), 5);
+        assert_eq!(profile.edge_count(0x1002, 0x1009), 2);
+        assert_eq!(profile.edge_count(0x1009, 0x100e), 1);
+        assert_eq!(profile.edge_count(0x1009, 0x100f), 1);
+        assert_eq!(profile.block_count(0x1002), 5);
+        assert_eq!(profile.block_count(0x100e), 1);
+
+        // the counts are carried into the VAG
+        let vag = VirtualAddressGraph::from_cfg_brstack(&cfg, brstack.as_bytes(), 0x555555554000)
+            .unwrap();
+        assert!(vag.has_profile());
+        assert_eq!(
+            vag.node_at_target(Vertex::Id(0x1002))
+                .edge_weight(Vertex::Id(0x1002)),
+            Some(5)
+        );
+
+        // a malformed entry is reported with its line
+        let broken = "0x555555555007/0x555555555002/P/-/-/0 0x55555555500g/0x1/P/-/-/0\n";
+        match parse_brstack(broken.as_bytes(), &cfg, 0x555555554000) {
+            Err(ProfileError::Parse { line, .. }) => assert_eq!(line, 1),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Display;
+
+use iced_x86::*;
+
+use crate::cfg::*;
+
+/// The code of a function re-encoded for a new order of its blocks.
+///
+/// # Fields
+///
+/// * `address`     - the virtual address the code is placed at;
+/// * `code`        - the bytes of the re-encoded instructions;
+/// * `blocks`      - the new address of every block by its old address;
+/// * `addresses`   - the new address of every kept instruction by its old address;
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelocatedCode {
+    pub address: u64,
+    pub code: Vec<u8>,
+    pub blocks: BTreeMap<u64, u64>,
+    pub addresses: BTreeMap<u64, u64>,
+}
+
+/// The errors that can arise while re-encoding a function.
+///
+/// # Variants
+///
+/// * `InvalidOrder`    - the order is not a permutation of the blocks of the graph;
+/// * `Encoding`        - an instruction can not be encoded at its new address;
+/// * `Elf`             - the ELF file can not be rewritten (see ElfWriter);
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteError {
+    InvalidOrder,
+    Encoding(String),
+    Elf(String),
+}
+
+impl Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidOrder => write!(f, "The order is not a permutation of the blocks"),
+            Self::Encoding(err) => write!(f, "Cannot encode the instructions: {err}"),
+            Self::Elf(err) => write!(f, "Cannot rewrite the ELF file: {err}"),
+        }
+    }
+}
+
+impl Error for RewriteError {}
+
+impl From<IcedError> for RewriteError {
+    fn from(err: IcedError) -> Self {
+        Self::Encoding(err.to_string())
+    }
+}
+
+// an unconditional jump to the given (old) address
+// note: the inserted instructions have no address (0), hence nothing can target them
+fn jump_to(target: u64) -> Result<Instruction, RewriteError> {
+    Ok(Instruction::with_branch(Code::Jmp_rel32_64, target)?)
+}
+
+// the instructions of a block for the given next block (if there is one) in the new order:
+//      - a conditional branch to the next block is inverted to branch to the fall-through,
+//      - a conditional branch is followed by a jump if neither target is the next block,
+//      - a jump to the next block is removed (unless that empties the block),
+//      - a jump to the fall-through is added if it is not the next block
+// note:    the branches target the old addresses, which the BlockEncoder fixes as the first
+//          instructions of the blocks keep their old addresses
+fn block_instructions(
+    block: &BasicBlock,
+    next: Option<u64>,
+) -> Result<Vec<Instruction>, RewriteError> {
+    let mut instructions: Vec<Instruction> = block.instructions().to_vec();
+    let Some(mut last) = instructions.pop() else {
+        return Ok(instructions);
+    };
+
+    match last.flow_control() {
+        FlowControl::ConditionalBranch if last.is_jcc_short_or_near() => {
+            let (taken, fallthrough) = (last.near_branch_target(), last.next_ip());
+            if next == Some(fallthrough) {
+                instructions.push(last);
+            } else if next == Some(taken) {
+                last.negate_condition_code();
+                last.set_near_branch64(fallthrough);
+                instructions.push(last);
+            } else {
+                instructions.push(last);
+                instructions.push(jump_to(fallthrough)?);
+            }
+        }
+        FlowControl::UnconditionalBranch if last.is_jmp_short_or_near() => {
+            if next != Some(last.near_branch_target()) || instructions.is_empty() {
+                instructions.push(last);
+            }
+        }
+        FlowControl::ConditionalBranch | FlowControl::Next | FlowControl::Call => {
+            let fallthrough = last.next_ip();
+            instructions.push(last);
+            if next != Some(fallthrough) {
+                instructions.push(jump_to(fallthrough)?);
+            }
+        }
+        _ => instructions.push(last),
+    }
+
+    Ok(instructions)
+}
+
+/// Re-encodes the instructions of the blocks of the given control flow graph in the given
+/// order at the given address. The branches are fixed to the new addresses of their targets
+/// (the ones outside of the graph keep targeting their original addresses), the jumps to
+/// the next block are removed, the conditional branches to the next block are inverted, and
+/// jumps are inserted where a fall-through is broken by the new order.
+///
+/// # Arguments
+///
+/// * `cfg`     - the control flow graph of the function;
+/// * `order`   - the addresses of the blocks in their new order;
+/// * `address` - the virtual address where the new code is placed;
+///
+/// # Errors
+///
+/// `InvalidOrder` if the order is not a permutation of the blocks, and `Encoding` if an
+/// instruction can not be encoded at its new address.
+///
+pub fn relocate(
+    cfg: &ControlFlowGraph,
+    order: &[u64],
+    address: u64,
+) -> Result<RelocatedCode, RewriteError> {
+    let blocks: HashMap<u64, &BasicBlock> = cfg.blocks().iter().map(|b| (b.address(), b)).collect();
+    let mut sorted: Vec<u64> = order.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    if sorted.len() != order.len() || sorted.len() != blocks.len() {
+        return Err(RewriteError::InvalidOrder);
+    }
+
+    // the instructions of the blocks and the index of the first one of every block
+    let mut instructions: Vec<Instruction> = Vec::new();
+    let mut starts: Vec<(u64, usize)> = Vec::new();
+    for (p, old) in order.iter().enumerate() {
+        let block = blocks.get(old).ok_or(RewriteError::InvalidOrder)?;
+        starts.push((*old, instructions.len()));
+        instructions.extend(block_instructions(block, order.get(p + 1).copied())?);
+    }
+
+    let result = BlockEncoder::encode(
+        64,
+        InstructionBlock::new(&instructions, address),
+        BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS,
+    )?;
+    let offsets = &result.new_instruction_offsets;
+
+    let addresses: BTreeMap<u64, u64> = instructions
+        .iter()
+        .zip(offsets)
+        .filter(|(instr, &offset)| instr.ip() != 0 && offset != u32::MAX)
+        .map(|(instr, &offset)| (instr.ip(), address + offset as u64))
+        .collect();
+    let blocks: BTreeMap<u64, u64> = starts
+        .iter()
+        .filter_map(|&(old, index)| {
+            let offset = *offsets.get(index)?;
+            (offset != u32::MAX).then_some((old, address + offset as u64))
+        })
+        .collect();
+
+    Ok(RelocatedCode {
+        address,
+        code: result.code_buffer,
+        blocks,
+        addresses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the address, the mnemonic and the branch target of the instructions of the code
+    fn disassemble(relocated: &RelocatedCode) -> Vec<(u64, Mnemonic, u64)> {
+        Decoder::with_ip(64, &relocated.code, relocated.address, DecoderOptions::NONE)
+            .into_iter()
+            .map(|x| (x.ip(), x.mnemonic(), x.near_branch_target()))
+            .collect()
+    }
+
+    #[test]
+    fn inverted_branch() {
+        let cfg = counting_loop_cfg();
+        let relocated = relocate(&cfg, &[0x1000, 0x1002, 0x1009, 0x100f, 0x100e], 0x2000).unwrap();
+
+        // je to the next block becomes jne to the old fall-through
+        assert_eq!(
+            disassemble(&relocated),
+            vec![
+                (0x2000, Mnemonic::Xor, 0),
+                (0x2002, Mnemonic::Inc, 0),
+                (0x2004, Mnemonic::Cmp, 0),
+                (0x2007, Mnemonic::Jl, 0x2002),
+                (0x2009, Mnemonic::Test, 0),
+                (0x200c, Mnemonic::Jne, 0x2010),
+                (0x200e, Mnemonic::Ud2, 0),
+                (0x2010, Mnemonic::Ret, 0),
+            ]
+        );
+        assert_eq!(
+            relocated.blocks,
+            BTreeMap::from([
+                (0x1000, 0x2000),
+                (0x1002, 0x2002),
+                (0x1009, 0x2009),
+                (0x100e, 0x2010),
+                (0x100f, 0x200e)
+            ])
+        );
+        assert_eq!(relocated.addresses.get(&0x1004), Some(&0x2004));
+    }
+
+    #[test]
+    fn inserted_jumps() {
+        let cfg = counting_loop_cfg();
+        let relocated = relocate(&cfg, &[0x1000, 0x1009, 0x100e, 0x100f, 0x1002], 0x2000).unwrap();
+
+        // the broken fall-throughs of 0x1000 and 0x1002 need jumps
+        assert_eq!(
+            disassemble(&relocated),
+            vec![
+                (0x2000, Mnemonic::Xor, 0),
+                (0x2002, Mnemonic::Jmp, 0x200c),
+                (0x2004, Mnemonic::Test, 0),
+                (0x2007, Mnemonic::Je, 0x200a),
+                (0x2009, Mnemonic::Ret, 0),
+                (0x200a, Mnemonic::Ud2, 0),
+                (0x200c, Mnemonic::Inc, 0),
+                (0x200e, Mnemonic::Cmp, 0),
+                (0x2011, Mnemonic::Jl, 0x200c),
+                (0x2013, Mnemonic::Jmp, 0x2004),
+            ]
+        );
+
+        // the order has to be a permutation of the blocks
+        assert_eq!(
+            relocate(&cfg, &[0x1000, 0x1009, 0x100e, 0x100f], 0x2000),
+            Err(RewriteError::InvalidOrder)
+        );
+        assert_eq!(
+            relocate(&cfg, &[0x1000, 0x1009, 0x100e, 0x100f, 0x1009], 0x2000),
+            Err(RewriteError::InvalidOrder)
+        );
+    }
+}
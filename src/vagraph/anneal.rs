@@ -0,0 +1,151 @@
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::vagraph::cost::Objective;
+use crate::vagraph::local::Move;
+use crate::vagraph::vag::*;
+
+/// The parameters of the simulated annealing search over block orders.
+///
+/// # Fields
+///
+/// * `seed`                - the seed of the random number generator (same seed, same result);
+/// * `iterations`          - the number of random moves tried;
+/// * `time_limit`          - the search stops after this much time even if iterations remain;
+/// * `start_temperature`   - the temperature at the first iteration;
+/// * `end_temperature`     - the temperature at the last iteration (the cooling is geometric);
+///
+/// The temperatures must satisfy 0 <= end_temperature <= start_temperature, other values are
+/// clamped to this range (see AnnealConfig::temperatures). At a zero temperature only the
+/// moves which do not increase the cost are accepted.
+///
+#[derive(Debug, Clone)]
+pub struct AnnealConfig {
+    pub seed: u64,
+    pub iterations: usize,
+    pub time_limit: Option<Duration>,
+    pub start_temperature: f64,
+    pub end_temperature: f64,
+}
+
+impl Default for AnnealConfig {
+    fn default() -> Self {
+        AnnealConfig {
+            seed: 0,
+            iterations: 100_000,
+            time_limit: None,
+            start_temperature: 100.0,
+            end_temperature: 0.1,
+        }
+    }
+}
+
+impl AnnealConfig {
+    // the start and end temperatures clamped to 0 <= end <= start (NaNs are taken as zero)
+    pub fn temperatures(&self) -> (f64, f64) {
+        let start = self.start_temperature.max(0.0);
+        (start, self.end_temperature.max(0.0).min(start))
+    }
+}
+
+#[derive(Debug)]
+pub struct Annealing<'a, N: VAGNodeId> {
+    graph: &'a VirtualAddressGraph<N>,
+    config: &'a AnnealConfig,
+    // the cost that is decreased
+    objective: Objective,
+}
+
+impl<'a, N: VAGNodeId> Annealing<'a, N> {
+    // generates an Annealing instance for a VAG with the given parameters and objective
+    pub fn new(
+        graph: &'a VirtualAddressGraph<N>,
+        config: &'a AnnealConfig,
+        objective: Objective,
+    ) -> Self {
+        Annealing {
+            graph,
+            config,
+            objective,
+        }
+    }
+
+    // a uniformly chosen move on an order of n blocks, which keeps the first block in place
+    // note: n must be at least 3
+    fn random_move(rng: &mut ChaCha8Rng, n: usize) -> Move {
+        let i = rng.gen_range(1..n - 1);
+        let j = rng.gen_range(i + 1..n);
+
+        match rng.gen_range(0..5) {
+            0 => Move::Shift(i, j),
+            1 => Move::Shift(j, i),
+            2 => Move::Swap(i, j),
+            3 => Move::Reverse(i, j),
+            _ => Move::Exchange(i, rng.gen_range(i + 1..=j), j),
+        }
+    }
+
+    // simulated annealing: a random move is always accepted if it does not increase the cost,
+    // otherwise it is accepted with probability exp(-increase / temperature)
+    // returns the best order found during the search
+    // note: the first block of the given order stays in place
+    pub fn anneal(&self, order: &[N]) -> Vec<N> {
+        let mut current = self.objective.evaluator(self.graph, order);
+        let mut best: Vec<N> = order.to_vec();
+        let mut best_cost: usize = current.cost();
+
+        let (iterations, n) = (self.config.iterations, order.len());
+        if n < 3 || iterations == 0 {
+            return best;
+        }
+
+        let mut rng = ChaCha8Rng::seed_from_u64(self.config.seed);
+        let started = Instant::now();
+        // note: a zero start temperature stays zero (instead of a 0 / 0 cooling factor)
+        let (start, end) = self.config.temperatures();
+        let cooling: f64 = if start > 0.0 {
+            (end / start).powf(1.0 / iterations as f64)
+        } else {
+            0.0
+        };
+
+        let mut temperature: f64 = start;
+
+        for iteration in 0..iterations {
+            if self
+                .config
+                .time_limit
+                .is_some_and(|limit| started.elapsed() >= limit)
+            {
+                log::info!("annealing stopped by the time limit at iteration {iteration}");
+                break;
+            }
+
+            let step = Self::random_move(&mut rng, n);
+            let increase = current.delta(step);
+
+            if increase <= 0 || rng.gen::<f64>() < (-increase as f64 / temperature).exp() {
+                current.apply(step);
+
+                if current.cost() < best_cost {
+                    best = current.order();
+                    best_cost = current.cost();
+                }
+            }
+
+            if (iteration + 1) % (iterations / 10).max(1) == 0 {
+                log::info!(
+                    "annealing iteration {}/{iterations}: temperature {temperature:.3}, cost {}, best {best_cost}",
+                    iteration + 1,
+                    current.cost()
+                );
+            }
+
+            temperature *= cooling;
+        }
+
+        best
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vagraph::cost::IncrementalCost;
+use crate::vagraph::local::Move;
+use crate::vagraph::vag::*;
+
+/// Where the laid out code lives in memory and the sizes of the cache lines and pages.
+///
+/// # Fields
+///
+/// * `base`        - the address the code is placed at (rounded up to the alignment);
+/// * `alignment`   - the alignment of the start of the code in bytes;
+/// * `line_size`   - the size of a cache line in bytes;
+/// * `page_size`   - the size of a page in bytes;
+/// * `hot_percent` - an edge is hot if its weight is at least this percentage of the heaviest
+///   edge's weight (100: only the heaviest edges are hot, see VirtualAddressGraph::hot_blocks);
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub base: u64,
+    pub alignment: u64,
+    pub line_size: u64,
+    pub page_size: u64,
+    #[serde(default = "default_hot_percent")]
+    pub hot_percent: u64,
+}
+
+// half of the heaviest edge's weight: the statically estimated weights of the edges leaving
+// the loops (see LOOP_WEIGHT) stay cold
+fn default_hot_percent() -> u64 {
+    50
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            base: 0,
+            alignment: 16,
+            line_size: 64,
+            page_size: 4096,
+            hot_percent: default_hot_percent(),
+        }
+    }
+}
+
+impl CacheConfig {
+    // whether an edge of the given weight is hot, given the weight of the heaviest edge
+    pub fn is_hot(&self, weight: usize, heaviest: usize) -> bool {
+        weight as u128 * 100 >= heaviest as u128 * self.hot_percent.min(100) as u128
+    }
+
+    // the address of the first block
+    fn start(&self) -> u64 {
+        self.base.div_ceil(self.alignment.max(1)) * self.alignment.max(1)
+    }
+}
+
+/// The memory footprint of the hot blocks of an order (see VirtualAddressGraph::hot_blocks).
+///
+/// # Fields
+///
+/// * `lines`               - the number of distinct cache lines touched by the hot blocks;
+/// * `pages`               - the number of distinct pages touched by the hot blocks;
+/// * `crossing_branches`   - the number of taken branches between hot blocks whose source and target are in different cache lines;
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheCost {
+    pub lines: usize,
+    pub pages: usize,
+    pub crossing_branches: usize,
+}
+
+impl Display for CacheCost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cache lines, {} pages, {} line crossing branches",
+            self.lines, self.pages, self.crossing_branches
+        )
+    }
+}
+
+impl CacheCost {
+    // the objective minimised by the sorters: every touched line and page and every
+    // crossing branch counts one
+    pub fn total(&self) -> usize {
+        self.lines + self.pages + self.crossing_branches
+    }
+
+    // the cache cost of the given order of the VAG's nodes, where the blocks are laid out
+    // one after the other from the start address with their sizes in bytes
+    // note:    an edge is a taken branch if its target is not the next block - the branch
+    //          is at the last byte of the source and lands at the first byte of the target
+    pub fn of_order<N: VAGNodeId>(
+        vag: &VirtualAddressGraph<N>,
+        order: &[N],
+        hot: &BTreeSet<Vertex<N>>,
+        config: &CacheConfig,
+    ) -> Self {
+        let (line_size, page_size) = (config.line_size.max(1), config.page_size.max(1));
+
+        // the first and the last address of the blocks (the last one is None for empty blocks)
+        let mut address: HashMap<Vertex<N>, (u64, Option<u64>)> = HashMap::new();
+        let mut start: u64 = config.start();
+        for &block in order {
+            let size = vag.node_at_target(Vertex::Id(block)).size(SizeUnit::Bytes) as u64;
+            address.insert(
+                Vertex::Id(block),
+                (start, size.checked_sub(1).map(|s| start + s)),
+            );
+            start += size;
+        }
+
+        let mut lines: HashSet<u64> = HashSet::new();
+        let mut pages: HashSet<u64> = HashSet::new();
+        let mut cost = CacheCost::default();
+
+        for (p, &block) in order.iter().enumerate() {
+            let id = Vertex::Id(block);
+            if !hot.contains(&id) {
+                continue;
+            }
+
+            let (first, last) = address[&id];
+            let Some(last) = last else {
+                continue;
+            };
+            lines.extend(first / line_size..=last / line_size);
+            pages.extend(first / page_size..=last / page_size);
+
+            let next: Option<Vertex<N>> = order.get(p + 1).map(|&x| Vertex::Id(x));
+            for target in vag.node_at_target(id).targets() {
+                if Some(*target) != next
+                    && hot.contains(target)
+                    && last / line_size != address[target].0 / line_size
+                {
+                    cost.crossing_branches += 1;
+                }
+            }
+        }
+
+        cost.lines = lines.len();
+        cost.pages = pages.len();
+        cost
+    }
+}
+
+// the cache cost of an order of the blocks of a VAG, as the objective of the searches
+// note: there is no cheap way to update the footprint, hence delta recomputes it
+#[derive(Debug, Clone)]
+pub struct CacheOrderCost<'a, N: VAGNodeId> {
+    graph: &'a VirtualAddressGraph<N>,
+    hot: BTreeSet<Vertex<N>>,
+    config: CacheConfig,
+    order: Vec<N>,
+    cost: usize,
+}
+
+impl<'a, N: VAGNodeId> CacheOrderCost<'a, N> {
+    // generates a CacheOrderCost instance for the given order of the VAG's nodes
+    pub fn new(graph: &'a VirtualAddressGraph<N>, order: &[N], config: CacheConfig) -> Self {
+        let hot = graph.hot_blocks(&config);
+        let cost = CacheCost::of_order(graph, order, &hot, &config).total();
+
+        CacheOrderCost {
+            graph,
+            hot,
+            config,
+            order: order.to_vec(),
+            cost,
+        }
+    }
+}
+
+impl<N: VAGNodeId> IncrementalCost<N> for CacheOrderCost<'_, N> {
+    fn cost(&self) -> usize {
+        self.cost
+    }
+
+    fn order(&self) -> Vec<N> {
+        self.order.clone()
+    }
+
+    fn delta(&self, step: Move) -> isize {
+        let mut moved: Vec<N> = self.order.clone();
+        step.apply(&mut moved);
+
+        let cost = CacheCost::of_order(self.graph, &moved, &self.hot, &self.config).total();
+        cost as isize - self.cost as isize
+    }
+
+    fn apply(&mut self, step: Move) {
+        step.apply(&mut self.order);
+        self.cost = CacheCost::of_order(self.graph, &self.order, &self.hot, &self.config).total();
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use crate::vagraph::vag::*;
+
+// an edge of the graph together with its weight
+type WeightedEdge<N> = ((Vertex<N>, Vertex<N>), usize);
+
+// a chain of blocks: a sequence that is meant to be laid out consecutively
+// note: edges inside a chain (between neighbouring blocks) become fall-throughs
+#[derive(Debug)]
+struct Chain<N: VAGNodeId> {
+    blocks: Vec<Vertex<N>>,
+}
+
+impl<N: VAGNodeId> Chain<N> {
+    // the first block of the chain
+    fn head(&self) -> Vertex<N> {
+        self.blocks[0]
+    }
+
+    // the last block of the chain
+    fn tail(&self) -> Vertex<N> {
+        *self.blocks.last().unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub struct ChainGraph<N: VAGNodeId> {
+    address: Vertex<N>,
+    // the edges of the graph together with their weights
+    // sorted descending by weight (and then ascending by endpoints to be deterministic)
+    edges: Vec<WeightedEdge<N>>,
+    // the chains built so far - a merged chain leaves an empty vector behind
+    chains: Vec<Chain<N>>,
+    // which chain a given block belongs to at the moment
+    chain_of: HashMap<Vertex<N>, usize>,
+}
+
+impl<N: VAGNodeId> ChainGraph<N> {
+    // generates a ChainGraph instance from a VAG and a weight for each of its edges
+    // note: edges missing from the weights are considered to be weight 0
+    pub fn from_vag(vag: &VirtualAddressGraph<N>, weights: &EdgeWeights<N>) -> Self {
+        let mut edges: Vec<WeightedEdge<N>> = Vec::new();
+        let mut chains: Vec<Chain<N>> = Vec::new();
+        let mut chain_of: HashMap<Vertex<N>, usize> = HashMap::new();
+
+        let mut blocks: Vec<Vertex<N>> = vag.nodes().keys().copied().collect();
+        blocks.sort();
+
+        // initially every block forms a chain on its own
+        for block in blocks {
+            for target in vag.node_at_target(block).targets() {
+                let weight = weights.get(&(block, *target)).copied().unwrap_or(0);
+                edges.push(((block, *target), weight));
+            }
+
+            chain_of.insert(block, chains.len());
+            chains.push(Chain {
+                blocks: vec![block],
+            });
+        }
+
+        edges.sort_by(|(e, w), (f, v)| v.cmp(w).then(e.cmp(f)));
+
+        ChainGraph {
+            address: vag.address(),
+            edges,
+            chains,
+            chain_of,
+        }
+    }
+
+    // bottom-up phase: going through the edges from the heaviest to the lightest
+    // we glue two chains together whenever the edge goes from the tail of a chain
+    // to the head of another one
+    // note: the entry block must remain the head of its chain
+    fn merge_chains(&mut self) {
+        for &((source, target), _) in &self.edges {
+            let from = self.chain_of[&source];
+            let to = self.chain_of[&target];
+
+            if from == to
+                || target == self.address
+                || self.chains[from].tail() != source
+                || self.chains[to].head() != target
+            {
+                continue;
+            }
+
+            let moved = std::mem::take(&mut self.chains[to].blocks);
+            for block in &moved {
+                self.chain_of.insert(*block, from);
+            }
+            self.chains[from].blocks.extend(moved);
+        }
+    }
+
+    // the chains are placed one after the other starting with the entry's chain
+    // the next chain is always the one that is the most heavily connected to the
+    // already placed blocks (ties: smallest head address)
+    fn place_chains(&self) -> Vec<Vertex<N>> {
+        let mut order: Vec<Vertex<N>> = Vec::new();
+        // the total weight of the edges between the placed blocks and a not yet placed chain
+        let mut connection: HashMap<usize, usize> = HashMap::new();
+        let mut remaining: Vec<usize> = (0..self.chains.len())
+            .filter(|&c| !self.chains[c].blocks.is_empty())
+            .collect();
+
+        let mut next: usize = self.chain_of[&self.address];
+
+        loop {
+            remaining.retain(|&c| c != next);
+            order.extend(&self.chains[next].blocks);
+
+            for &((source, target), weight) in &self.edges {
+                let (from, to) = (self.chain_of[&source], self.chain_of[&target]);
+                if from == next && to != next {
+                    *connection.entry(to).or_insert(0) += weight;
+                } else if to == next && from != next {
+                    *connection.entry(from).or_insert(0) += weight;
+                }
+            }
+
+            match remaining.iter().max_by(|&&a, &&b| {
+                let wa = connection.get(&a).copied().unwrap_or(0);
+                let wb = connection.get(&b).copied().unwrap_or(0);
+                wa.cmp(&wb)
+                    .then(self.chains[b].head().cmp(&self.chains[a].head()))
+            }) {
+                Some(&chain) => next = chain,
+                None => break,
+            }
+        }
+
+        order
+    }
+
+    // an implementation of Pettis and Hansen's bottom-up chain formation for
+    // basic block placement: the heavy edges are turned into fall-throughs
+    pub fn pettis_hansen(&mut self) -> Vec<Vertex<N>> {
+        self.merge_chains();
+        self.place_chains()
+    }
+}
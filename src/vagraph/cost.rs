@@ -0,0 +1,446 @@
+use std::cmp::*;
+use std::collections::HashMap;
+
+use crate::vagraph::cache::{CacheConfig, CacheOrderCost};
+use crate::vagraph::local::Move;
+use crate::vagraph::vag::*;
+
+/// The objective minimised by the searches over block orders and reported by cfg_cost.
+///
+/// # Variants
+///
+/// * `Distance`    - the total length of the jumps (see cfg_cost) with the sizes in the given unit;
+/// * `Cache`       - the cache lines and pages touched by the hot blocks plus the taken branches crossing cache lines (see CacheCost);
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    Distance(SizeUnit),
+    Cache(CacheConfig),
+}
+
+impl Default for Objective {
+    fn default() -> Self {
+        Objective::Distance(SizeUnit::Instructions)
+    }
+}
+
+impl Objective {
+    // the cost of the given order of the VAG's nodes, kept up to date while the order changes
+    pub fn evaluator<'a, N: VAGNodeId>(
+        &self,
+        vag: &'a VirtualAddressGraph<N>,
+        order: &[N],
+    ) -> Box<dyn IncrementalCost<N> + 'a> {
+        match *self {
+            Objective::Distance(unit) => Box::new(OrderCost::with_unit(vag, order, unit)),
+            Objective::Cache(config) => Box::new(CacheOrderCost::new(vag, order, config)),
+        }
+    }
+}
+
+// the cost of an order that the searches can query and update move by move
+pub trait IncrementalCost<N> {
+    // the cost of the current order
+    fn cost(&self) -> usize;
+
+    // the current order of the blocks
+    fn order(&self) -> Vec<N>;
+
+    // the change of the cost if the given move was applied to the current order
+    fn delta(&self, step: Move) -> isize;
+
+    // applies the given move to the current order and updates the cost
+    fn apply(&mut self, step: Move);
+}
+
+// the cost of an order of the blocks of a VAG, kept up to date while the order changes
+// the blocks are indexed by their positions in the initial order, that is: the index of a
+// block never changes, only its position does
+// note:    with the positions and the prefix sums of the lengths at hand the cost of an edge
+//          is computed in O(1), hence the cost of the whole order in O(E)
+// note:    the cost of an edge is the length of the jump it needs in the order
+//          - fall-through (the target is the next block): 0, no jump is needed
+//          - forward edge: the total length of the blocks jumped over (endpoints excluded)
+//          - backward edge: the total length of the blocks from the target to the source
+//            (endpoints included), since the jump goes from the end of the source back to the
+//            beginning of the target
+//          - self-loop: 0, its jump is the same in every order, hence it is not charged
+//          - 2-cycle (back-and-forth edges): the two edges are charged independently, that is
+//            if they are adjacent, then one of them is a fall-through and the other one jumps
+//            back over both blocks
+//          hence the cost of an order is 0 iff every edge (except self-loops) is a fall-through
+// note:    the cost of an edge is multiplied by its frequency (see edge_frequencies), that is
+//          without a profile every edge counts once, with a profile the jumps never taken are free
+#[derive(Debug, Clone)]
+pub struct OrderCost<N: VAGNodeId> {
+    // the index of the block at a given position
+    order: Vec<usize>,
+    // the position of the block with a given index
+    position: Vec<usize>,
+    // the block with a given index
+    blocks: Vec<N>,
+    // the length of the block with a given index (in the unit of the cost)
+    lens: Vec<usize>,
+    // the indices of the targets of the block with a given index (with the edges' frequencies)
+    targets: Vec<Vec<(usize, usize)>>,
+    // the indices of the sources of the block with a given index (with the edges' frequencies)
+    sources: Vec<Vec<(usize, usize)>>,
+    // prefix[p] = the total length of the blocks before position p
+    prefix: Vec<usize>,
+    // the cost of the current order
+    cost: usize,
+}
+
+impl<N: VAGNodeId> OrderCost<N> {
+    // generates an OrderCost instance for the given order of the VAG's nodes
+    // note: the order must contain all the nodes of the VAG (and only those) exactly once
+    pub fn new(vag: &VirtualAddressGraph<N>, order: &[N]) -> Self {
+        Self::with_unit(vag, order, SizeUnit::Instructions)
+    }
+
+    // the same as new, but the lengths of the blocks are measured in the given unit
+    pub fn with_unit(vag: &VirtualAddressGraph<N>, order: &[N], unit: SizeUnit) -> Self {
+        let index: HashMap<Vertex<N>, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| (Vertex::Id(x), i))
+            .collect();
+
+        let frequencies = vag.edge_frequencies();
+        let mut lens: Vec<usize> = Vec::new();
+        let mut targets: Vec<Vec<(usize, usize)>> = vec![Vec::new(); order.len()];
+        let mut sources: Vec<Vec<(usize, usize)>> = vec![Vec::new(); order.len()];
+
+        for (i, &block) in order.iter().enumerate() {
+            lens.push(vag.node_at_target(Vertex::Id(block)).size(unit));
+
+            for target in vag.node_at_target(Vertex::Id(block)).targets() {
+                let j = index[target];
+                let frequency = frequencies[&(Vertex::Id(block), *target)];
+                targets[i].push((j, frequency));
+                sources[j].push((i, frequency));
+            }
+        }
+
+        let mut ordercost = OrderCost {
+            order: (0..order.len()).collect(),
+            position: (0..order.len()).collect(),
+            blocks: order.to_vec(),
+            lens,
+            targets,
+            sources,
+            prefix: Vec::new(),
+            cost: 0,
+        };
+        ordercost.update_prefix();
+        ordercost.cost = ordercost.full_cost();
+
+        ordercost
+    }
+
+    // recomputes the prefix sums of the lengths for the current order
+    fn update_prefix(&mut self) {
+        self.prefix = vec![0];
+        for &i in &self.order {
+            self.prefix.push(self.prefix.last().unwrap() + self.lens[i]);
+        }
+    }
+
+    // the weight of an edge going from the block at position pos01 to the block at position pos02
+    // where prefix(p) is the total length of the blocks before position p
+    // note: an edge goes from the last instruction of a block to the first instruction of the other block
+    fn edge_cost<F: Fn(usize) -> usize>(pos01: usize, pos02: usize, prefix: F) -> usize {
+        match pos01.cmp(&pos02) {
+            // edge goes forward: pos01 and pos02 are not counted
+            Ordering::Less => prefix(pos02) - prefix(pos01 + 1),
+            // edge goes backward: pos01 and pos02 are counted
+            Ordering::Greater => prefix(pos01 + 1) - prefix(pos02),
+            // loop edge: the same in every order, not charged
+            Ordering::Equal => 0,
+        }
+    }
+
+    // the cost of the current order computed from scratch
+    fn full_cost(&self) -> usize {
+        let mut cost: usize = 0;
+
+        for (i, targets) in self.targets.iter().enumerate() {
+            for &(j, frequency) in targets {
+                cost += frequency
+                    * Self::edge_cost(self.position[i], self.position[j], |p| self.prefix[p]);
+            }
+        }
+
+        cost
+    }
+}
+
+impl<N: VAGNodeId> IncrementalCost<N> for OrderCost<N> {
+    fn cost(&self) -> usize {
+        self.cost
+    }
+
+    fn order(&self) -> Vec<N> {
+        self.order.iter().map(|&i| self.blocks[i]).collect()
+    }
+
+    // the change of the cost if the given move was applied to the current order
+    // note:    a move rearranges the blocks inside a window of positions only, hence the
+    //          edges with no endpoint in the window keep their cost (even the ones jumping
+    //          over the window, since the same blocks are in there) - the runtime is linear
+    //          in the size of the window plus the number of edges touching the window
+    fn delta(&self, step: Move) -> isize {
+        let (lo, hi) = step.window();
+
+        // the new arrangement of the window
+        let mut window: Vec<usize> = self.order[lo..=hi].to_vec();
+        step.shifted(lo).apply(&mut window);
+
+        let mut new_position: HashMap<usize, usize> = HashMap::new();
+        let mut new_prefix: Vec<usize> = vec![self.prefix[lo]];
+        for (k, &i) in window.iter().enumerate() {
+            new_position.insert(i, lo + k);
+            new_prefix.push(new_prefix[k] + self.lens[i]);
+        }
+
+        let position = |i: usize| new_position.get(&i).copied().unwrap_or(self.position[i]);
+        let prefix = |p: usize| match lo < p && p <= hi {
+            true => new_prefix[p - lo],
+            false => self.prefix[p],
+        };
+
+        let mut delta: isize = 0;
+        for &i in &window {
+            let edges = self.targets[i].iter().map(|&(j, f)| (i, j, f)).chain(
+                self.sources[i]
+                    .iter()
+                    // the edges inside the window are counted at their sources
+                    .filter(|&&(j, _)| !new_position.contains_key(&j))
+                    .map(|&(j, f)| (j, i, f)),
+            );
+
+            for (s, t, frequency) in edges {
+                let old = Self::edge_cost(self.position[s], self.position[t], |p| self.prefix[p]);
+                let new = Self::edge_cost(position(s), position(t), prefix);
+                delta += frequency as isize * (new as isize - old as isize);
+            }
+        }
+
+        delta
+    }
+
+    fn apply(&mut self, step: Move) {
+        let delta = self.delta(step);
+        let (lo, hi) = step.window();
+
+        step.apply(&mut self.order);
+        for p in lo..=hi {
+            self.position[self.order[p]] = p;
+        }
+        self.update_prefix();
+
+        self.cost = (self.cost as isize + delta) as usize;
+        debug_assert_eq!(self.cost, self.full_cost());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    // the VAG of the random blocks, the entry is 0x0
+    fn vag_from(blocks: &[(u64, usize, Vec<u64>)]) -> VirtualAddressGraph<u64> {
+        let blocks: Vec<(u64, usize, &[u64])> = blocks
+            .iter()
+            .map(|(address, len, targets)| (*address, *len, targets.as_slice()))
+            .collect();
+        vag_from_blocks(0x0, &blocks)
+    }
+
+    // a random graph on the blocks 0..n with lengths 1..=8 and at most 3 targets per block
+    // (self-loops and back-and-forth edges included)
+    fn random_blocks(rng: &mut ChaCha8Rng, n: u64) -> Vec<(u64, usize, Vec<u64>)> {
+        (0..n)
+            .map(|address| {
+                let mut targets: Vec<u64> = (0..rng.gen_range(0..=3))
+                    .map(|_| rng.gen_range(0..n))
+                    .collect();
+                targets.sort();
+                targets.dedup();
+                (address, rng.gen_range(1..=8), targets)
+            })
+            .collect()
+    }
+
+    // the cost of an order straight from the definition (see the note at OrderCost)
+    fn reference_cost(blocks: &[(u64, usize, Vec<u64>)], order: &[u64]) -> usize {
+        let len = |x: u64| blocks.iter().find(|b| b.0 == x).unwrap().1;
+        let pos = |x: u64| order.iter().position(|&y| y == x).unwrap();
+
+        let mut cost: usize = 0;
+        for (address, _, targets) in blocks {
+            for &target in targets {
+                let (p, q) = (pos(*address), pos(target));
+                if p < q {
+                    cost += order[p + 1..q].iter().map(|&x| len(x)).sum::<usize>();
+                } else if p > q {
+                    cost += order[q..=p].iter().map(|&x| len(x)).sum::<usize>();
+                }
+            }
+        }
+
+        cost
+    }
+
+    // TEST: delta() agrees with the cost computed from scratch for all the moves
+    #[test]
+    fn delta_matches_full_cost() {
+        let blocks: Vec<(u64, usize, Vec<u64>)> = vec![
+            (0x0, 2, vec![0x1, 0x4]),
+            (0x1, 5, vec![0x2, 0x3]),
+            (0x2, 1, vec![0x1, 0x2]),
+            (0x3, 3, vec![0x5, 0x0]),
+            (0x4, 7, vec![0x3, 0x5]),
+            (0x5, 2, vec![]),
+        ];
+        let vag = vag_from(&blocks);
+
+        let order: Vec<u64> = vec![0x0, 0x3, 0x1, 0x5, 0x2, 0x4];
+        let ordercost = OrderCost::new(&vag, &order);
+        assert_eq!(ordercost.cost(), ordercost.full_cost());
+
+        let n = order.len();
+        let moves = (1..n).flat_map(|i| {
+            (i + 1..n).flat_map(move |j| {
+                [
+                    Move::Shift(i, j),
+                    Move::Shift(j, i),
+                    Move::Swap(i, j),
+                    Move::Reverse(i, j),
+                ]
+                .into_iter()
+                .chain((i + 1..=j).map(move |k| Move::Exchange(i, k, j)))
+            })
+        });
+
+        for step in moves {
+            let mut moved = order.clone();
+            step.apply(&mut moved);
+
+            let expected = OrderCost::new(&vag, &moved).cost() as isize - ordercost.cost() as isize;
+            assert_eq!(ordercost.delta(step), expected, "{step:?}");
+        }
+    }
+
+    // TEST: the cost agrees with the definition on random graphs and orders
+    #[test]
+    fn cost_matches_definition() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..200 {
+            let blocks = random_blocks(&mut rng, 8);
+            let vag = vag_from(&blocks);
+
+            let mut order: Vec<u64> = (0..8).collect();
+            order.shuffle(&mut rng);
+            assert_eq!(vag.cost_of_order(&order), reference_cost(&blocks, &order));
+        }
+    }
+
+    // TEST: the cost is 0 iff every edge except the self-loops is a fall-through
+    #[test]
+    fn zero_cost_iff_chain_in_order() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..200 {
+            // a chain in a random order with random self-loops: cost 0
+            let mut order: Vec<u64> = (0..8).collect();
+            order.shuffle(&mut rng);
+            let mut blocks: Vec<(u64, usize, Vec<u64>)> = order
+                .iter()
+                .enumerate()
+                .map(|(p, &x)| {
+                    let mut targets: Vec<u64> = order.get(p + 1).copied().into_iter().collect();
+                    if rng.gen_bool(0.3) {
+                        targets.push(x);
+                    }
+                    (x, rng.gen_range(1..=8), targets)
+                })
+                .collect();
+            assert_eq!(vag_from(&blocks).cost_of_order(&order), 0);
+
+            // any other edge is a jump
+            let (p, q) = (rng.gen_range(0..8), rng.gen_range(0..8));
+            if p == q || p + 1 == q {
+                continue;
+            }
+            blocks[p].2.push(order[q]);
+            assert!(vag_from(&blocks).cost_of_order(&order) > 0);
+        }
+
+        // and conversely: on random graphs a zero cost means fall-throughs only
+        for _ in 0..200 {
+            let blocks = random_blocks(&mut rng, 5);
+            let mut order: Vec<u64> = (0..5).collect();
+            order.shuffle(&mut rng);
+
+            let chain = blocks.iter().all(|(x, _, targets)| {
+                let p = order.iter().position(|y| y == x).unwrap();
+                targets
+                    .iter()
+                    .all(|t| t == x || order.get(p + 1) == Some(t))
+            });
+            assert_eq!(vag_from(&blocks).cost_of_order(&order) == 0, chain);
+        }
+    }
+
+    // TEST: self-loops do not change the cost of any order
+    #[test]
+    fn self_loops_are_free() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        for _ in 0..200 {
+            let blocks = random_blocks(&mut rng, 8);
+            let looped: Vec<(u64, usize, Vec<u64>)> = blocks
+                .iter()
+                .map(|(x, len, targets)| {
+                    let mut targets = targets.clone();
+                    if !targets.contains(x) {
+                        targets.push(*x);
+                    }
+                    (*x, *len, targets)
+                })
+                .collect();
+
+            let mut order: Vec<u64> = (0..8).collect();
+            order.shuffle(&mut rng);
+            assert_eq!(
+                vag_from(&blocks).cost_of_order(&order),
+                vag_from(&looped).cost_of_order(&order)
+            );
+        }
+    }
+
+    // TEST: adjacent back-and-forth edges cost the jump back over both blocks
+    #[test]
+    fn back_and_forth_edges() {
+        let blocks: Vec<(u64, usize, Vec<u64>)> = vec![
+            (0x0, 2, vec![0x1]),
+            (0x1, 3, vec![0x2]),
+            (0x2, 5, vec![0x1, 0x3]),
+            (0x3, 7, vec![]),
+        ];
+        let vag = vag_from(&blocks);
+
+        // 0x1 -> 0x2 falls through, 0x2 -> 0x1 jumps back over both
+        assert_eq!(vag.cost_of_order(&[0x0, 0x1, 0x2, 0x3]), 3 + 5);
+        // 0x0 -> 0x1 jumps over 0x2, 0x2 -> 0x1 falls through, 0x1 -> 0x2 jumps back over both
+        // and 0x2 -> 0x3 jumps over 0x1
+        assert_eq!(vag.cost_of_order(&[0x0, 0x2, 0x1, 0x3]), 5 + (5 + 3) + 3);
+        // separated: both directions jump over 0x3, and 0x2 -> 0x3 jumps back
+        assert_eq!(
+            vag.cost_of_order(&[0x0, 0x1, 0x3, 0x2]),
+            7 + (3 + 7 + 5) + (7 + 5)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vagraph::vag::*;
+
+// the sizes of the x86 branch encodings in bytes
+const JMP_REL8: usize = 2;
+const JMP_REL32: usize = 5;
+const JCC_REL8: usize = 2;
+const JCC_REL32: usize = 6;
+
+/// The size of the code and the number of taken branches of an order of blocks, once the
+/// branches at the ends of the blocks are re-encoded for that order.
+///
+/// # Fields
+///
+/// * `code_size`       - the size of the laid out code in bytes;
+/// * `taken_branches`  - the number of edges that are not fall-throughs, i.e. need a branch;
+/// * `jumps`           - the number of unconditional jumps in the laid out code;
+/// * `conditionals`    - the number of conditional branches in the laid out code;
+/// * `near_branches`   - the number of branches whose displacement does not fit in rel8;
+/// * `removed_jumps`   - the number of unconditional jumps turned into fall-throughs;
+/// * `inverted`        - the number of inverted conditional branches (if fall-throughs are known);
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodingCost {
+    pub code_size: usize,
+    pub taken_branches: usize,
+    pub jumps: usize,
+    pub conditionals: usize,
+    pub near_branches: usize,
+    pub removed_jumps: usize,
+    pub inverted: usize,
+}
+
+impl Display for EncodingCost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes, {} taken branches ({} jumps, {} conditionals, {} near)",
+            self.code_size, self.taken_branches, self.jumps, self.conditionals, self.near_branches
+        )
+    }
+}
+
+// a branch instruction at the end of a block in the new layout
+#[derive(Debug)]
+struct Branch {
+    // the position of the target block in the order
+    target: usize,
+    conditional: bool,
+    // whether it needs a 32 bit displacement
+    near: bool,
+}
+
+impl Branch {
+    fn size(&self) -> usize {
+        match (self.conditional, self.near) {
+            (false, false) => JMP_REL8,
+            (false, true) => JMP_REL32,
+            (true, false) => JCC_REL8,
+            (true, true) => JCC_REL32,
+        }
+    }
+}
+
+impl EncodingCost {
+    // the encoding cost of the given order of the VAG's nodes
+    // note:    the blocks end as follows (decided by the number of targets)
+    //          - no targets: return, indirect jump, etc. - nothing to re-encode
+    //          - one target: a jump, which is deleted if the target is the next block
+    //            (a block closed by an unconditional jump has only the jump's target here,
+    //            see NodeWeight::jump - the next instruction is not its successor)
+    //          - two targets: a conditional branch, which is inverted if its target is the
+    //            next block, and is followed by an extra jump if neither of them is next
+    //          - more targets (e.g. a condensed component): kept as it is, an indirect jump
+    //          the original branch instruction (see NodeWeight::branch_size) is replaced by
+    //          the new ones - the displacements are relaxed from rel8 to rel32 until they fit
+    pub fn of_order<N: VAGNodeId>(vag: &VirtualAddressGraph<N>, order: &[N]) -> Self {
+        let position: HashMap<Vertex<N>, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| (Vertex::Id(x), i))
+            .collect();
+
+        let mut cost = EncodingCost::default();
+        // the size of the block without its branches and the branches emitted after it
+        let mut bodies: Vec<usize> = Vec::new();
+        let mut branches: Vec<Vec<Branch>> = Vec::new();
+
+        for (p, &block) in order.iter().enumerate() {
+            let node = vag.node_at_target(Vertex::Id(block));
+            let next: Option<Vertex<N>> = order.get(p + 1).map(|&x| Vertex::Id(x));
+            let original = node.branch().unwrap_or(0);
+
+            let targets: Vec<Vertex<N>> = match node.jump() {
+                Some(target) => vec![target],
+                None => node.targets().iter().copied().collect(),
+            };
+
+            let mut emitted: Vec<Branch> = Vec::new();
+            let jump = |target: Vertex<N>, conditional: bool| Branch {
+                target: position[&target],
+                conditional,
+                near: false,
+            };
+
+            match targets[..] {
+                [] => (),
+                [target] => {
+                    if Some(target) == next {
+                        cost.removed_jumps += usize::from(original > 0);
+                    } else {
+                        emitted.push(jump(target, false));
+                    }
+                }
+                [first, second] => {
+                    // the branch's own target (the other one is the fall-through)
+                    let (fallthrough, taken) = match node.fallthrough() {
+                        Some(target) if target == second => (second, first),
+                        _ => (first, second),
+                    };
+
+                    if Some(fallthrough) == next {
+                        emitted.push(jump(taken, true));
+                    } else if Some(taken) == next {
+                        cost.inverted += usize::from(node.fallthrough().is_some());
+                        emitted.push(jump(fallthrough, true));
+                    } else {
+                        emitted.push(jump(taken, true));
+                        emitted.push(jump(fallthrough, false));
+                    }
+                }
+                _ => cost.taken_branches += 1,
+            }
+
+            let body = match emitted.is_empty() && targets.len() > 2 {
+                true => node.size(SizeUnit::Bytes),
+                false => node.size(SizeUnit::Bytes).saturating_sub(original),
+            };
+
+            bodies.push(body);
+            branches.push(emitted);
+        }
+
+        // branch relaxation: the displacements only grow, hence this terminates
+        loop {
+            let mut address: Vec<usize> = Vec::new();
+            let mut end: usize = 0;
+            for (body, emitted) in bodies.iter().zip(&branches) {
+                address.push(end);
+                end += body + emitted.iter().map(Branch::size).sum::<usize>();
+            }
+
+            let mut changed: bool = false;
+            for (p, emitted) in branches.iter_mut().enumerate() {
+                let mut from = address[p] + bodies[p];
+                for branch in emitted.iter_mut() {
+                    from += branch.size();
+                    let displacement = address[branch.target] as i64 - from as i64;
+                    if !branch.near && i8::try_from(displacement).is_err() {
+                        branch.near = true;
+                        changed = true;
+                    }
+                }
+            }
+
+            if !changed {
+                cost.code_size = end;
+                break;
+            }
+        }
+
+        for branch in branches.iter().flatten() {
+            cost.taken_branches += 1;
+            cost.near_branches += usize::from(branch.near);
+            match branch.conditional {
+                true => cost.conditionals += 1,
+                false => cost.jumps += 1,
+            }
+        }
+
+        cost
+    }
+}
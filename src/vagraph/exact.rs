@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use crate::vagraph::vag::*;
+
+// the largest graph we are willing to solve exactly: the dynamic programming below
+// needs a table with 2^n entries
+pub const OPTIMAL_MAX_NODES: usize = 20;
+
+// the blocks of a VAG indexed by 0..n, the neighbourhoods are stored as bitmasks
+#[derive(Debug)]
+pub struct ExactGraph<N: VAGNodeId> {
+    // the block at the given index
+    blocks: Vec<Vertex<N>>,
+    // the number of instructions of the block at the given index
+    lens: Vec<usize>,
+    // the bitmask of targets of the block at the given index
+    targets: Vec<u32>,
+    // the bitmask of sources of the block at the given index
+    sources: Vec<u32>,
+    // the frequency of the edge between the blocks at the given indices (see edge_frequencies)
+    frequencies: Vec<Vec<usize>>,
+    // the index of the entry block
+    entry: usize,
+}
+
+impl<N: VAGNodeId> ExactGraph<N> {
+    // generates an ExactGraph instance from a VAG
+    // note: the VAG can not have more than OPTIMAL_MAX_NODES nodes
+    pub fn from_vag(vag: &VirtualAddressGraph<N>) -> Self {
+        assert!(
+            vag.nodes().len() <= OPTIMAL_MAX_NODES,
+            "the graph is too large to be solved exactly"
+        );
+
+        let mut blocks: Vec<Vertex<N>> = vag.nodes().keys().copied().collect();
+        blocks.sort();
+
+        let index: HashMap<Vertex<N>, usize> =
+            blocks.iter().enumerate().map(|(i, &b)| (b, i)).collect();
+
+        let mut lens: Vec<usize> = Vec::new();
+        let mut targets: Vec<u32> = vec![0; blocks.len()];
+        let mut sources: Vec<u32> = vec![0; blocks.len()];
+        let mut frequencies: Vec<Vec<usize>> = vec![vec![0; blocks.len()]; blocks.len()];
+
+        let edge_frequencies = vag.edge_frequencies();
+        for (i, block) in blocks.iter().enumerate() {
+            let node = vag.node_at_target(*block);
+            lens.push(vag.weight(*block));
+
+            for target in node.targets() {
+                let j = index[target];
+                targets[i] |= 1 << j;
+                sources[j] |= 1 << i;
+                frequencies[i][j] = edge_frequencies[&(*block, *target)];
+            }
+        }
+
+        ExactGraph {
+            entry: index[&vag.address()],
+            blocks,
+            lens,
+            targets,
+            sources,
+            frequencies,
+        }
+    }
+
+    // the indices of the blocks in the given bitmask
+    fn indices(mut mask: u32) -> impl Iterator<Item = usize> {
+        std::iter::from_fn(move || {
+            let i = mask.trailing_zeros() as usize;
+            mask &= mask.wrapping_sub(1);
+            (i < 32).then_some(i)
+        })
+    }
+
+    // the total frequency of the edges from the blocks in the bitmask to the given block
+    fn frequency_from(&self, mask: u32, to: usize) -> usize {
+        Self::indices(mask & self.sources[to])
+            .map(|i| self.frequencies[i][to])
+            .sum()
+    }
+
+    // the total frequency of the edges from the given block to the blocks in the bitmask
+    fn frequency_to(&self, from: usize, mask: u32) -> usize {
+        Self::indices(mask & self.targets[from])
+            .map(|j| self.frequencies[from][j])
+            .sum()
+    }
+
+    // for every set of blocks (as a bitmask) computes the total frequency of the edges
+    // leaving the set (first vector) and entering the set (second vector)
+    // note: the table for a set is derived from the set without its highest block
+    fn crossing_edges(&self) -> (Vec<usize>, Vec<usize>) {
+        let size: usize = 1 << self.blocks.len();
+        let mut leaving: Vec<usize> = vec![0; size];
+        let mut entering: Vec<usize> = vec![0; size];
+
+        for set in 1..size {
+            let v = (usize::BITS - 1 - set.leading_zeros()) as usize;
+            let rest = (set & !(1 << v)) as u32;
+            let set = set as u32;
+
+            leaving[set as usize] =
+                leaving[rest as usize] - self.frequency_from(rest, v) + self.frequency_to(v, !set);
+            entering[set as usize] =
+                entering[rest as usize] - self.frequency_to(v, rest) + self.frequency_from(!set, v);
+        }
+
+        (leaving, entering)
+    }
+
+    // the order of the blocks with the minimal cost_of_order where the entry block comes first
+    // note:    the cost of an order is the sum over the blocks of their length times the total
+    //          frequency of the edges jumping over them - and whether an edge jumps over a block depends only on
+    //          the set of blocks placed before it, hence a dynamic programming over the subsets
+    //          of the blocks (Held-Karp style) gives the optimum
+    pub fn optimal(&self) -> (usize, Vec<Vertex<N>>) {
+        let n = self.blocks.len();
+        let (leaving, entering) = self.crossing_edges();
+
+        // cost[set]: the minimal cost of the blocks in set, if set is placed first
+        // last[set]: the last block of that optimal prefix
+        let mut cost: Vec<usize> = vec![usize::MAX; 1 << n];
+        let mut last: Vec<u8> = vec![0; 1 << n];
+
+        cost[0] = 0;
+        for set in 0_usize..(1 << n) {
+            if cost[set] == usize::MAX || (set != 0 && set & (1 << self.entry) == 0) {
+                continue;
+            }
+
+            for x in 0..n {
+                if set & (1 << x) != 0 || (set == 0 && x != self.entry) {
+                    continue;
+                }
+                let next = set | (1 << x);
+
+                // forward edges jumping over x: from set to the blocks placed after x
+                let forward = leaving[set] - self.frequency_from(set as u32, x);
+                // backward edges jumping over x: from x or later back to x or earlier
+                // note: a self-loop on x is not charged (see OrderCost)
+                let backward = entering[next] + self.frequency_to(x, set as u32);
+
+                let candidate = cost[set] + self.lens[x] * (forward + backward);
+                if candidate < cost[next] {
+                    cost[next] = candidate;
+                    last[next] = x as u8;
+                }
+            }
+        }
+
+        let mut order: Vec<Vertex<N>> = Vec::new();
+        let mut set: usize = (1 << n) - 1;
+        while set != 0 {
+            let x = last[set] as usize;
+            order.push(self.blocks[x]);
+            set &= !(1 << x);
+        }
+        order.reverse();
+
+        (cost[(1 << n) - 1], order)
+    }
+}
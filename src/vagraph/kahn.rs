@@ -1,5 +1,7 @@
-use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
 
+use crate::vagraph::tiebreak::*;
 use crate::vagraph::vag::*; //{VirtualAddressGraph, NoInstrBasicBlock};
 
 // use std::fmt::Display;
@@ -59,16 +61,45 @@ impl<'a, N: VAGNodeId> KahnBasicBlock<'a, N> {
     }
 }
 
+// a zero in-degree block in the heap of a tie-break which does not depend on the last placed
+// block: the greatest one is popped first (and from the equal ones the smallest address)
+struct ReadyBlock<'a, 'b, N: VAGNodeId, T: TieBreak<N> + ?Sized> {
+    block: &'a NoInstrBasicBlock<N>,
+    tie_break: &'b T,
+}
+
+impl<N: VAGNodeId, T: TieBreak<N> + ?Sized> Ord for ReadyBlock<'_, '_, N, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tie_break
+            .compare(None, self.block, other.block)
+            .then(other.block.address().cmp(&self.block.address()))
+    }
+}
+
+impl<N: VAGNodeId, T: TieBreak<N> + ?Sized> PartialOrd for ReadyBlock<'_, '_, N, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N: VAGNodeId, T: TieBreak<N> + ?Sized> PartialEq for ReadyBlock<'_, '_, N, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<N: VAGNodeId, T: TieBreak<N> + ?Sized> Eq for ReadyBlock<'_, '_, N, T> {}
+
 #[derive(Debug)]
 pub struct KahnGraph<'a, N: VAGNodeId> {
     address: Vertex<N>,
-    nodes: HashMap<Vertex<N>, KahnBasicBlock<'a, N>>,
+    nodes: BTreeMap<Vertex<N>, KahnBasicBlock<'a, N>>,
 }
 
 impl<'a, N: VAGNodeId> KahnGraph<'a, N> {
     // generates a KahnGraph instance from a VAG
     pub fn from_vag(vag: &'a VirtualAddressGraph<N>) -> Self {
-        let mut nodes: HashMap<Vertex<N>, KahnBasicBlock<N>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<N>, KahnBasicBlock<N>> = BTreeMap::new();
 
         for (node, block) in vag.nodes() {
             nodes.insert(*node, KahnBasicBlock::<N> { block, deleted: 0 });
@@ -88,12 +119,12 @@ impl<'a, N: VAGNodeId> KahnGraph<'a, N> {
     */
 
     // returns the slice of KBBs of the KahnGraph
-    fn nodes(&self) -> &HashMap<Vertex<N>, KahnBasicBlock<'a, N>> {
+    fn nodes(&self) -> &BTreeMap<Vertex<N>, KahnBasicBlock<'a, N>> {
         &self.nodes
     }
 
     // returns a mutable slice of KBBs of the KahnGraph
-    fn nodes_mut(&mut self) -> &mut HashMap<Vertex<N>, KahnBasicBlock<'a, N>> {
+    fn nodes_mut(&mut self) -> &mut BTreeMap<Vertex<N>, KahnBasicBlock<'a, N>> {
         &mut self.nodes
     }
 
@@ -144,27 +175,77 @@ impl<'a, N: VAGNodeId> KahnGraph<'a, N> {
         }
     }
 
+    // the initially zero in-degree blocks
+    fn sources(&self) -> Vec<&'a NoInstrBasicBlock<N>> {
+        self.nodes()
+            .values()
+            .filter(|kahnblock| kahnblock.indegree() == 0)
+            .map(|kahnblock| kahnblock.block())
+            .collect()
+    }
+
     // an implementation of the weighted version of Kahn's topological sorting algorithm
     // for directed acyclic graphs
-    // the weights are used for tie breaking when there are more than one vertex with
-    // zero indegree: sorted by two keys: original in-degree and then lengths of block
+    // the given strategy is used for tie breaking when there are more than one vertex with
+    // zero indegree (see TieBreak - by default they are sorted by two keys: original in-degree
+    // and then lengths of block), the remaining ties are broken by the smaller address
     // note:    the output vector must contain Vertex<N> elements since we will run it on
     //          the subgraphs obtained from strongly connected components
-    pub fn kahn_algorithm(&mut self) -> Vec<Vertex<N>> {
+    // note:    if the choice may depend on the last placed block (see TieBreak::uses_last),
+    //          then the zero in-degree vertices can not be kept in a heap
+    pub fn kahn_algorithm<T: TieBreak<N> + ?Sized>(&mut self, tie_break: &T) -> Vec<Vertex<N>> {
         // topsort: the topological order of the basic blocks - collecting only the addresses
-        let mut topsort: Vec<Vertex<N>> = Vec::new();
-        // an auxiliary vector: the zero in-degree vertices of the running algorithm
-        let mut visit: BinaryHeap<&NoInstrBasicBlock<N>> = BinaryHeap::new();
+        let topsort: Vec<Vertex<N>> = match tie_break.uses_last() {
+            true => self.kahn_by_search(tie_break),
+            false => self.kahn_by_heap(tie_break),
+        };
+
+        // for further use we decrease the deleted fields back to zero for all nodes
+        self.no_deleted();
+
+        // return topological order
+        topsort
+    }
 
-        // initialization: collect the initially zero in-degree vertices
-        // the binary heap orders them by length
-        for kahnblock in self.nodes().values() {
-            if kahnblock.indegree() == 0 {
-                visit.push(kahnblock.block());
+    // Kahn's algorithm where the zero in-degree vertices are kept in a binary heap
+    fn kahn_by_heap<T: TieBreak<N> + ?Sized>(&mut self, tie_break: &T) -> Vec<Vertex<N>> {
+        let mut topsort: Vec<Vertex<N>> = Vec::new();
+        // an auxiliary heap: the zero in-degree vertices of the running algorithm
+        let mut visit: BinaryHeap<ReadyBlock<N, T>> = self
+            .sources()
+            .into_iter()
+            .map(|block| ReadyBlock { block, tie_break })
+            .collect();
+
+        while let Some(ReadyBlock { block: node, .. }) = visit.pop() {
+            // reduce the in-degrees of the actual vertex's target(s)
+            for target in node.targets() {
+                if let Some(block) = self.reduce_indegree(target) {
+                    visit.push(ReadyBlock { block, tie_break });
+                }
             }
+
+            topsort.push(node.address());
         }
 
-        while let Some(node) = visit.pop() {
+        topsort
+    }
+
+    // Kahn's algorithm where the best zero in-degree vertex is searched for in every step,
+    // since the comparison depends on the last placed block
+    fn kahn_by_search<T: TieBreak<N> + ?Sized>(&mut self, tie_break: &T) -> Vec<Vertex<N>> {
+        let mut topsort: Vec<Vertex<N>> = Vec::new();
+        // an auxiliary vector: the zero in-degree vertices of the running algorithm
+        let mut visit: Vec<&'a NoInstrBasicBlock<N>> = self.sources();
+        let mut last: Option<&'a NoInstrBasicBlock<N>> = None;
+
+        while let Some(best) = (0..visit.len()).max_by(|&i, &j| {
+            tie_break
+                .compare(last, visit[i], visit[j])
+                .then(visit[j].address().cmp(&visit[i].address()))
+        }) {
+            let node = visit.swap_remove(best);
+
             // reduce the in-degrees of the actual vertex's target(s)
             for target in node.targets() {
                 if let Some(block) = self.reduce_indegree(target) {
@@ -173,12 +254,9 @@ impl<'a, N: VAGNodeId> KahnGraph<'a, N> {
             }
 
             topsort.push(node.address());
+            last = Some(node);
         }
 
-        // for further use we decrease the deleted fields back to zero for all nodes
-        self.no_deleted();
-
-        // return topological order
         topsort
     }
 }
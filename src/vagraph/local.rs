@@ -0,0 +1,128 @@
+use std::cmp::{max, min};
+
+use crate::vagraph::cost::Objective;
+use crate::vagraph::vag::*;
+
+// the modifications of an order that the local search tries
+// note: position 0 is never touched, that is where the entry block lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+    // the block at the first position is moved to the second position
+    Shift(usize, usize),
+    // the blocks at the two positions are swapped
+    Swap(usize, usize),
+    // the segment between the two positions (inclusive) is reversed
+    Reverse(usize, usize),
+    // the two consecutive segments [i, k) and [k, j] change places
+    Exchange(usize, usize, usize),
+}
+
+impl Move {
+    // modifies the given order according to the move
+    pub fn apply<T>(&self, order: &mut Vec<T>) {
+        match *self {
+            Move::Shift(from, to) => {
+                let block = order.remove(from);
+                order.insert(to, block);
+            }
+            Move::Swap(i, j) => order.swap(i, j),
+            Move::Reverse(i, j) => order[i..=j].reverse(),
+            Move::Exchange(i, k, j) => order[i..=j].rotate_left(k - i),
+        }
+    }
+
+    // the first and the last position touched by the move
+    pub fn window(&self) -> (usize, usize) {
+        match *self {
+            Move::Shift(from, to) => (min(from, to), max(from, to)),
+            Move::Swap(i, j) | Move::Reverse(i, j) | Move::Exchange(i, _, j) => (i, j),
+        }
+    }
+
+    // the same move with all the positions decreased by offset
+    pub fn shifted(&self, offset: usize) -> Move {
+        match *self {
+            Move::Shift(from, to) => Move::Shift(from - offset, to - offset),
+            Move::Swap(i, j) => Move::Swap(i - offset, j - offset),
+            Move::Reverse(i, j) => Move::Reverse(i - offset, j - offset),
+            Move::Exchange(i, k, j) => Move::Exchange(i - offset, k - offset, j - offset),
+        }
+    }
+
+    // all the possible moves on an order of n blocks, which keep the first block in place
+    fn all(n: usize) -> impl Iterator<Item = Move> {
+        let shifts = (1..n).flat_map(move |i| {
+            (1..n)
+                .filter(move |&j| j != i && j != i + 1)
+                .map(move |j| Move::Shift(i, j))
+        });
+        let swaps = (1..n).flat_map(move |i| (i + 1..n).map(move |j| Move::Swap(i, j)));
+        let reverses = (1..n).flat_map(move |i| (i + 2..n).map(move |j| Move::Reverse(i, j)));
+        // the exchanges where one of the segments is a single block are already shifts
+        let exchanges = (1..n).flat_map(move |i| {
+            (i + 2..n).flat_map(move |k| (k + 1..n).map(move |j| Move::Exchange(i, k, j)))
+        });
+
+        shifts.chain(swaps).chain(reverses).chain(exchanges)
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalSearch<'a, N: VAGNodeId> {
+    graph: &'a VirtualAddressGraph<N>,
+    // the maximal number of orders whose cost is evaluated
+    budget: usize,
+    // the cost that is decreased
+    objective: Objective,
+}
+
+impl<'a, N: VAGNodeId> LocalSearch<'a, N> {
+    // generates a LocalSearch instance for a VAG with a given evaluation budget and objective
+    pub fn new(graph: &'a VirtualAddressGraph<N>, budget: usize, objective: Objective) -> Self {
+        LocalSearch {
+            graph,
+            budget,
+            objective,
+        }
+    }
+
+    // first-improvement hill climbing: applies the first move that decreases the cost of
+    // the order, and repeats until no move improves or the budget is exhausted
+    // note: the first block of the given order stays in place
+    pub fn improve(&self, order: &[N]) -> Vec<N> {
+        let mut ordercost = self.objective.evaluator(self.graph, order);
+        let mut evaluations: usize = 0;
+
+        'search: loop {
+            let mut improved: bool = false;
+
+            for step in Move::all(order.len()) {
+                if evaluations == self.budget {
+                    break 'search;
+                }
+                evaluations += 1;
+
+                let delta = ordercost.delta(step);
+                if delta < 0 {
+                    log::debug!(
+                        "{step:?}: cost {} -> {}",
+                        ordercost.cost(),
+                        ordercost.cost() as isize + delta
+                    );
+                    ordercost.apply(step);
+                    improved = true;
+                }
+            }
+
+            if !improved {
+                break;
+            }
+        }
+
+        log::debug!(
+            "local search stopped after {evaluations} evaluations with cost {}",
+            ordercost.cost()
+        );
+        ordercost.order()
+    }
+}
@@ -1,3 +1,12 @@
+pub mod anneal;
+pub mod cache;
+pub mod chain;
+pub mod cost;
+pub mod encoding;
+pub mod exact;
 pub mod kahn;
+pub mod local;
 pub mod scc;
+pub mod split;
+pub mod tiebreak;
 pub mod vag;
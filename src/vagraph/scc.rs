@@ -2,7 +2,7 @@
 // use std::hash::Hash;
 
 use petgraph::algo::tarjan_scc;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::vagraph::vag::*;
 
@@ -11,7 +11,7 @@ pub struct Component<'a, N: VAGNodeId> {
     // the original graph
     graph: &'a VirtualAddressGraph<N>,
     // the strongly connected component
-    component: HashSet<Vertex<N>>,
+    component: BTreeSet<Vertex<N>>,
     // identifier of the component: smallest nodeid
     compid: Vertex<N>,
 }
@@ -25,7 +25,7 @@ impl<'a, N: VAGNodeId> Component<'a, N> {
         let scc: Vec<Vec<Vertex<N>>> = tarjan_scc(vag);
 
         for comp in scc {
-            let mut strongly: HashSet<Vertex<N>> = HashSet::new();
+            let mut strongly: BTreeSet<Vertex<N>> = BTreeSet::new();
             for node in &comp {
                 // TODO: if let not ??
                 match strongly.insert(*node) {
@@ -57,7 +57,7 @@ impl<'a, N: VAGNodeId> Component<'a, N> {
     }
 
     // returns the collection of nodes in the strongly connnected component
-    pub fn nodes(&self) -> &HashSet<Vertex<N>> {
+    pub fn nodes(&self) -> &BTreeSet<Vertex<N>> {
         &self.component
     }
 
@@ -72,7 +72,7 @@ impl<'a, N: VAGNodeId> Component<'a, N> {
     }
 
     // returns a reference to the targets of a given vertex in the component
-    fn targets(&self, node: Vertex<N>) -> &HashSet<Vertex<N>> {
+    fn targets(&self, node: Vertex<N>) -> &BTreeSet<Vertex<N>> {
         self.whole().node_at_target(node).targets()
     }
 
@@ -116,7 +116,7 @@ impl<'a, N: VAGNodeId> Component<'a, N> {
     pub fn to_acyclic_vag(&self) -> VirtualAddressGraph<N> {
         // let address = self.nodes().iter().min().unwrap();
 
-        let mut nodes: HashMap<Vertex<N>, NoInstrBasicBlock<N>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<N>, NoInstrBasicBlock<N>> = BTreeMap::new();
         for node in self.nodes() {
             // TODO: do this without clone()
             // MAYBE: rewrite the whole Kahn's algorithm to accept avoided edges, vertices, etc.
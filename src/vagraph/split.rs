@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use crate::vagraph::vag::*;
+
+/// The blocks of an order split into a hot and a cold region, both in the original order.
+///
+/// # Fields
+///
+/// * `hot`     - the blocks executed more often than the threshold (the entry is always hot);
+/// * `cold`    - the blocks executed at most threshold times (e.g. never executed for 0);
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HotColdSplit<N> {
+    pub hot: Vec<N>,
+    pub cold: Vec<N>,
+}
+
+impl<N: VAGNodeId> HotColdSplit<N> {
+    // splits the given order of the VAG's nodes by their execution counts
+    // note:    without a profile (no block counts and no edge weights) nothing can be told
+    //          about the blocks, hence every block is hot
+    pub fn of_order(vag: &VirtualAddressGraph<N>, order: &[N], threshold: usize) -> Self {
+        let counts = execution_counts(vag);
+        let mut split = HotColdSplit {
+            hot: Vec::new(),
+            cold: Vec::new(),
+        };
+
+        for &block in order {
+            let id = Vertex::Id(block);
+            let cold = id != vag.address() && counts.get(&id).is_some_and(|&c| c <= threshold);
+            match cold {
+                true => split.cold.push(block),
+                false => split.hot.push(block),
+            }
+        }
+
+        split
+    }
+
+    // the hot region followed by the cold one
+    pub fn order(&self) -> Vec<N> {
+        self.hot.iter().chain(self.cold.iter()).copied().collect()
+    }
+}
+
+// the execution counts of the blocks: the measured ones if they are known, otherwise the sum
+// of the weights of the incoming edges (the blocks are entered as many times as executed)
+// note: empty if there is no profile at all
+fn execution_counts<N: VAGNodeId>(vag: &VirtualAddressGraph<N>) -> HashMap<Vertex<N>, usize> {
+    let counted = vag.nodes().values().any(|node| node.count().is_some());
+    if !counted && !vag.has_profile() {
+        return HashMap::new();
+    }
+
+    let mut incoming: HashMap<Vertex<N>, usize> = HashMap::new();
+    for node in vag.nodes().values() {
+        for target in node.targets() {
+            *incoming.entry(*target).or_insert(0) += node.edge_weight(*target).unwrap_or(0);
+        }
+    }
+
+    vag.nodes()
+        .iter()
+        .map(|(id, node)| {
+            let count = node
+                .count()
+                .unwrap_or_else(|| incoming.get(id).copied().unwrap_or(0));
+            (*id, count)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_by_counts() {
+        // 0x0 -> {0x1, 0x2} -> 0x3, where 0x1 is an error path
+        let vag = vag_from_blocks(
+            0x0,
+            &[
+                (0x0, 1, &[0x1, 0x2]),
+                (0x1, 1, &[0x3]),
+                (0x2, 1, &[0x3]),
+                (0x3, 1, &[]),
+            ],
+        );
+        let order = [0x0, 0x1, 0x2, 0x3];
+
+        // without a profile every block is hot
+        let split = HotColdSplit::of_order(&vag, &order, 0);
+        assert_eq!(split.hot, order);
+        assert!(split.cold.is_empty());
+
+        // the edge weights tell the counts of the blocks without a measured count
+        let counts: HashMap<Vertex<u64>, usize> = [(Vertex::Id(0x0), 0), (Vertex::Id(0x3), 40)]
+            .into_iter()
+            .collect();
+        let weights: EdgeWeights<u64> = [
+            ((0x0, 0x2), 40),
+            ((0x2, 0x3), 40),
+            ((0x0, 0x1), 0),
+            ((0x1, 0x3), 0),
+        ]
+        .into_iter()
+        .map(|((from, to), w)| ((Vertex::Id(from), Vertex::Id(to)), w))
+        .collect();
+        let vag = vag.with_profile(&counts, &weights);
+
+        // the entry stays hot, even if it was not counted
+        let split = HotColdSplit::of_order(&vag, &order, 0);
+        assert_eq!(split.hot, [0x0, 0x2, 0x3]);
+        assert_eq!(split.cold, [0x1]);
+        assert_eq!(split.order(), [0x0, 0x2, 0x3, 0x1]);
+
+        // everything below the threshold is cold
+        let split = vag.split_order(&order, 40);
+        assert_eq!(split.hot, [0x0]);
+        assert_eq!(split.cold, [0x1, 0x2, 0x3]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::cmp::Ordering;
+
+use crate::vagraph::vag::*;
+
+/// The choice between two blocks that are both ready to be placed in a step of Kahn's
+/// algorithm (i.e. their incoming edges are all deleted): the greater one is placed next.
+/// Note that the blocks a tie-break finds equal are placed in ascending order by their
+/// addresses, hence the order is reproducible.
+///
+/// # Arguments
+///
+/// * `last`    - the block placed in the previous step (None before the first step);
+/// * `a`, `b`  - the two blocks to choose from;
+///
+/// Every `Fn(Option<&NoInstrBasicBlock<N>>, &NoInstrBasicBlock<N>, &NoInstrBasicBlock<N>) -> Ordering`
+/// closure is a tie-break too.
+///
+/// A tie-break that ignores `last` should say so by `uses_last`: then the ready blocks are
+/// kept in a heap, otherwise the best one is searched for in every step (which is quadratic).
+///
+pub trait TieBreak<N: VAGNodeId> {
+    fn compare(
+        &self,
+        last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering;
+
+    // whether the choice may depend on the last placed block (closures: assumed that it may)
+    fn uses_last(&self) -> bool {
+        true
+    }
+}
+
+impl<N, F> TieBreak<N> for F
+where
+    N: VAGNodeId,
+    F: Fn(Option<&NoInstrBasicBlock<N>>, &NoInstrBasicBlock<N>, &NoInstrBasicBlock<N>) -> Ordering,
+{
+    fn compare(
+        &self,
+        last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        self(last, a, b)
+    }
+}
+
+/// The default tie-break of cfg_sort: the block with the most incoming edges (originally)
+/// and then the one with the most instructions is placed first.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DegreeLength;
+
+impl<N: VAGNodeId> TieBreak<N> for DegreeLength {
+    fn compare(
+        &self,
+        _last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        a.indegree().cmp(&b.indegree()).then(a.len().cmp(&b.len()))
+    }
+
+    fn uses_last(&self) -> bool {
+        false
+    }
+}
+
+/// The blocks are placed in their original order: the smallest address first.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddressOrder;
+
+impl<N: VAGNodeId> TieBreak<N> for AddressOrder {
+    fn compare(
+        &self,
+        _last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        b.address().cmp(&a.address())
+    }
+
+    fn uses_last(&self) -> bool {
+        false
+    }
+}
+
+// the weight of the edge from the last placed block to the given one: the profiled count if
+// there is one, otherwise 1 if there is an edge at all
+fn incoming_weight<N: VAGNodeId>(
+    last: Option<&NoInstrBasicBlock<N>>,
+    block: &NoInstrBasicBlock<N>,
+) -> usize {
+    let Some(last) = last else {
+        return 0;
+    };
+    match last.targets().contains(&block.address()) {
+        true => last.edge_weight(block.address()).unwrap_or(1),
+        false => 0,
+    }
+}
+
+/// The block reached by the heaviest edge from the last placed block is placed first (see
+/// NodeWeight), the rest of the ties are broken as by DegreeLength.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IncomingWeight;
+
+impl<N: VAGNodeId> TieBreak<N> for IncomingWeight {
+    fn compare(
+        &self,
+        last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        incoming_weight(last, a)
+            .cmp(&incoming_weight(last, b))
+            .then(DegreeLength.compare(last, a, b))
+    }
+}
+
+/// The original fall-through of the last placed block is placed first (see NodeWeight), the
+/// rest of the ties are broken as by DegreeLength.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreferFallthrough;
+
+impl<N: VAGNodeId> TieBreak<N> for PreferFallthrough {
+    fn compare(
+        &self,
+        last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        let fallthrough = last.and_then(|x| x.fallthrough());
+        (fallthrough == Some(a.address()))
+            .cmp(&(fallthrough == Some(b.address())))
+            .then(DegreeLength.compare(last, a, b))
+    }
+}
+
+/// A successor of the last placed block is placed first: the one reached by the heaviest
+/// edge (see NodeWeight), and then its original fall-through (see NodeWeight). If none of
+/// the ready blocks is a successor, the ties are broken as by DegreeLength. This is the
+/// tie-break of the `Fallthrough` layout.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FollowSuccessor;
+
+// how much the given block is preferred as the next block after the last placed one: whether
+// it is a successor at all, the weight of the edge to it and whether it is the fall-through
+fn successor_rank<N: VAGNodeId>(
+    last: Option<&NoInstrBasicBlock<N>>,
+    block: &NoInstrBasicBlock<N>,
+) -> (bool, usize, bool) {
+    let Some(last) = last else {
+        return (false, 0, false);
+    };
+    let address = block.address();
+    match last.targets().contains(&address) {
+        true => (
+            true,
+            last.edge_weight(address).unwrap_or(0),
+            last.fallthrough() == Some(address),
+        ),
+        false => (false, 0, false),
+    }
+}
+
+impl<N: VAGNodeId> TieBreak<N> for FollowSuccessor {
+    fn compare(
+        &self,
+        last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        successor_rank(last, a)
+            .cmp(&successor_rank(last, b))
+            .then(DegreeLength.compare(last, a, b))
+    }
+}
@@ -1,10 +1,21 @@
 use std::cmp::*;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 // use crate::bbsort::NodeWeight;
 use crate::cfg::*;
+use crate::estimate::*;
+use crate::profile::{autofdo, fdata, perf, EdgeProfile, ProfileError};
+use crate::vagraph::anneal::*;
+use crate::vagraph::cache::*;
+use crate::vagraph::chain::*;
+use crate::vagraph::cost::*;
+use crate::vagraph::encoding::*;
+use crate::vagraph::exact::*;
 use crate::vagraph::kahn::*;
+use crate::vagraph::local::*;
 use crate::vagraph::scc::*;
+use crate::vagraph::split::*;
+use crate::vagraph::tiebreak::*;
 
 use std::default::Default;
 use std::fmt::{Debug, Display, LowerHex};
@@ -21,8 +32,68 @@ use petgraph::visit::*;
 pub trait NodeWeight {
     type Node;
     fn weight(&self, node: Self::Node) -> usize;
+
+    // the size of the block in bytes - if it is known
+    fn byte_size(&self, _node: Self::Node) -> Option<usize> {
+        None
+    }
+
+    // the size in bytes of the branch instruction at the end of the block - if it is known
+    // and the block ends with a direct (conditional or unconditional) branch
+    fn branch_size(&self, _node: Self::Node) -> Option<usize> {
+        None
+    }
+
+    // the block's original fall-through successor - if it is known and there is one
+    fn fallthrough(&self, _node: Self::Node) -> Option<Self::Node> {
+        None
+    }
+
+    // the target of the unconditional jump at the end of the block - if it is known and the
+    // block ends with one (then the other successors of the block are not its real successors)
+    fn jump(&self, _node: Self::Node) -> Option<Self::Node> {
+        None
+    }
+
+    // how many times the edge from the first node to the second is taken - if it is known
+    // note: counts or probabilities (scaled to integers) - only their ratios matter
+    fn edge_weight(&self, _from: Self::Node, _to: Self::Node) -> Option<usize> {
+        None
+    }
+
+    // how many times the block is executed - if it is known
+    fn execution_count(&self, _node: Self::Node) -> Option<usize> {
+        None
+    }
+
+    // the size of the block in the given unit
+    // note: if the size in bytes is not known, then it falls back to the weight
+    fn size(&self, node: Self::Node, unit: SizeUnit) -> usize
+    where
+        Self::Node: Copy,
+    {
+        match unit {
+            SizeUnit::Instructions => self.weight(node),
+            SizeUnit::Bytes => self.byte_size(node).unwrap_or_else(|| self.weight(node)),
+        }
+    }
 }
 
+// the unit in which the size of a block (and hence the length of a jump) is measured
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SizeUnit {
+    #[default]
+    Instructions,
+    Bytes,
+}
+
+// how many times more often an edge inside a loop is taken than an edge outside of it
+// (used for static estimation of edge weights whenever there is no profile)
+pub const LOOP_WEIGHT: usize = 10;
+
+// weights of the edges of a graph, e.g. how many times an edge is taken
+pub type EdgeWeights<N> = HashMap<(Vertex<N>, Vertex<N>), usize>;
+
 pub trait VAGNodeId: Copy + Eq + Debug + Hash + Ord {}
 
 impl<T: Copy + Eq + Debug + Hash + Ord> VAGNodeId for T {}
@@ -92,14 +163,32 @@ pub struct NoInstrBasicBlock<N: VAGNodeId> {
     address: Vertex<N>,
     // the number of instructions in the block
     len: usize,
+    // the size of the block in bytes (if known)
+    #[serde(default)]
+    bytes: Option<usize>,
+    // the size of the closing branch instruction in bytes (if known and there is one)
+    #[serde(default)]
+    branch: Option<usize>,
+    // the target we get to without jumping, i.e. the block right after this one originally
+    // note: a missing Option field is deserialized as None
+    fallthrough: Option<Vertex<N>>,
+    // the target of the closing unconditional jump (if known and there is one)
+    // note: the targets of such a block may contain the next block too (see BasicBlock)
+    jump: Option<Vertex<N>>,
+    // the number of times the block is executed (if known, e.g. from a profile)
+    #[serde(default)]
+    count: Option<usize>,
+    // the number of times the edges to the targets are taken (if known, e.g. from a profile)
+    #[serde(default = "HashMap::new")]
+    weights: HashMap<Vertex<N>, usize>,
     // the addresses of block from which we can jump to the current block
     // that is: sources = all the direct predecessors of the block
     // note: indegree = #sources !!! (otherwise the block is invalid)
-    sources: HashSet<Vertex<N>>,
+    sources: BTreeSet<Vertex<N>>,
     // the addresses of blocks where we will jump next
     // that is: targets = all the direct successors of the block
     // note: its length is at most two
-    targets: HashSet<Vertex<N>>,
+    targets: BTreeSet<Vertex<N>>,
     // number of blocks from we jump to here
     indegree: usize,
 }
@@ -110,13 +199,19 @@ impl<N: VAGNodeId> NoInstrBasicBlock<N> {
     pub fn new(
         address: Vertex<N>,
         len: usize,
-        sources: HashSet<Vertex<N>>,
-        targets: HashSet<Vertex<N>>,
+        sources: BTreeSet<Vertex<N>>,
+        targets: BTreeSet<Vertex<N>>,
         indegree: usize,
     ) -> Self {
         NoInstrBasicBlock::<N> {
             address,
             len,
+            bytes: None,
+            branch: None,
+            fallthrough: None,
+            jump: None,
+            count: None,
+            weights: HashMap::new(),
             sources,
             targets,
             indegree,
@@ -129,31 +224,115 @@ impl<N: VAGNodeId> NoInstrBasicBlock<N> {
     }
 
     // the number of instructions
-    fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.len
     }
 
-    // a hashset reference of target blocks' addresses
-    pub fn targets(&self) -> &HashSet<Vertex<N>> {
+    // whether the block has no instructions (e.g. the phantom Sink vertex)
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // the size of the block in bytes (if known)
+    pub fn bytes(&self) -> Option<usize> {
+        self.bytes
+    }
+
+    // sets the size of the block in bytes
+    pub fn with_bytes(mut self, bytes: usize) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    // the size of the closing branch instruction in bytes (if known and there is one)
+    pub fn branch(&self) -> Option<usize> {
+        self.branch
+    }
+
+    // sets the size of the closing branch instruction in bytes
+    pub fn with_branch(mut self, branch: usize) -> Self {
+        self.branch = Some(branch);
+        self
+    }
+
+    // the original fall-through target (if known and there is one)
+    pub fn fallthrough(&self) -> Option<Vertex<N>> {
+        self.fallthrough
+    }
+
+    // sets the original fall-through target
+    pub fn with_fallthrough(mut self, fallthrough: Vertex<N>) -> Self {
+        self.fallthrough = Some(fallthrough);
+        self
+    }
+
+    // the target of the closing unconditional jump (if known and there is one)
+    pub fn jump(&self) -> Option<Vertex<N>> {
+        self.jump
+    }
+
+    // sets the target of the closing unconditional jump
+    pub fn with_jump(mut self, jump: Vertex<N>) -> Self {
+        self.jump = Some(jump);
+        self
+    }
+
+    // the number of times the block is executed (if known)
+    pub fn count(&self) -> Option<usize> {
+        self.count
+    }
+
+    // sets the number of times the block is executed
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    // the number of times the edge to the given target is taken (if known)
+    pub fn edge_weight(&self, target: Vertex<N>) -> Option<usize> {
+        self.weights.get(&target).copied()
+    }
+
+    // sets the number of times the edge to the given target is taken
+    pub fn with_edge_weight(mut self, target: Vertex<N>, weight: usize) -> Self {
+        self.weights.insert(target, weight);
+        self
+    }
+
+    // whether any of the outgoing edges has a known weight
+    fn has_edge_weights(&self) -> bool {
+        !self.weights.is_empty()
+    }
+
+    // the size of the block in the given unit (in bytes: if known, otherwise the number of instructions)
+    pub fn size(&self, unit: SizeUnit) -> usize {
+        match unit {
+            SizeUnit::Instructions => self.len(),
+            SizeUnit::Bytes => self.bytes().unwrap_or(self.len()),
+        }
+    }
+
+    // a (sorted) set reference of target blocks' addresses
+    pub fn targets(&self) -> &BTreeSet<Vertex<N>> {
         &self.targets
     }
 
     // a mutable reference of target blocks' addresses
-    fn targets_mut(&mut self) -> &mut HashSet<Vertex<N>> {
+    fn targets_mut(&mut self) -> &mut BTreeSet<Vertex<N>> {
         &mut self.targets
     }
 
-    // a hashset reference of source blocks' addresses
-    pub fn sources(&self) -> &HashSet<Vertex<N>> {
+    // a (sorted) set reference of source blocks' addresses
+    pub fn sources(&self) -> &BTreeSet<Vertex<N>> {
         &self.sources
     }
 
-    fn set_sources(&mut self, sources: &HashSet<Vertex<N>>) {
+    fn set_sources(&mut self, sources: &BTreeSet<Vertex<N>>) {
         // takes the union of self.sources and sources
         self.sources.extend(sources);
     }
 
-    // deletes the given source from the sources hashset if it's there (which will hold all the time)
+    // deletes the given source from the sources set if it's there (which will hold all the time)
     // note: this also results in an indegree modification
     fn erase_source(&mut self, source: Vertex<N>) {
         if self.sources.remove(&source) {
@@ -163,7 +342,7 @@ impl<N: VAGNodeId> NoInstrBasicBlock<N> {
     }
 
     /*
-    // deletes the given source from the sources hashset if it's there
+    // deletes the given source from the sources set if it's there
     // BUT it does not change the indegree!!
     fn erase_source_but_keep_indegree(&mut self, source: Vertex<N>) {
         self.sources.remove(&source);
@@ -208,10 +387,10 @@ impl<N: VAGNodeId> NoInstrBasicBlock<N> {
 // translates a BasicBlock to NIBB, that is counts the number of instructions
 // TODO: is it any good for that specific choice - BB is my previous "dummy" struct
 // BasicBlock struct - not generic type !!
-// note: BB contains no information about the sourcing addresses -> sources: empty hashset
+// note: BB contains no information about the sourcing addresses -> sources: empty set
 impl NoInstrBasicBlock<u64> {
     fn from_bb(bb: &BasicBlock) -> Self {
-        let mut targets: HashSet<Vertex<u64>> = HashSet::new();
+        let mut targets: BTreeSet<Vertex<u64>> = BTreeSet::new();
         for target in bb.targets() {
             targets.insert(Vertex::Id(*target));
         }
@@ -219,7 +398,13 @@ impl NoInstrBasicBlock<u64> {
         NoInstrBasicBlock::<u64> {
             address: Vertex::Id(bb.address()),
             len: bb.instructions().len(),
-            sources: HashSet::<Vertex<u64>>::new(),
+            bytes: Some((bb.end_address() + 1 - bb.address()) as usize),
+            branch: bb.branch_size(),
+            fallthrough: bb.fallthrough().map(Vertex::Id),
+            jump: bb.jump_target().map(Vertex::Id),
+            count: None,
+            weights: HashMap::new(),
+            sources: BTreeSet::<Vertex<u64>>::new(),
             targets,
             indegree: 0_usize,
         }
@@ -240,6 +425,8 @@ impl<N: VAGNodeId> PartialEq for NoInstrBasicBlock<N> {
 impl<N: VAGNodeId> Eq for NoInstrBasicBlock<N> {}
 
 // order of NIBB's: first by the number of incoming edges then by the length of basic block
+// note: the ties are broken by the address (the smaller one is greater), hence two blocks are
+//       only equal if they are at the same address and a max-heap pops them in a fixed order
 // WHY: is this bound on N is needed ?
 impl<N: VAGNodeId> PartialOrd for NoInstrBasicBlock<N> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -252,6 +439,7 @@ impl<N: VAGNodeId> Ord for NoInstrBasicBlock<N> {
         self.indegree()
             .cmp(&other.indegree())
             .then(self.len().cmp(&other.len()))
+            .then(other.address().cmp(&self.address()))
     }
 }
 
@@ -262,7 +450,8 @@ impl<N: VAGNodeId> Ord for NoInstrBasicBlock<N> {
 pub struct VirtualAddressGraph<N: VAGNodeId> {
     // start: N - TODO!
     address: Vertex<N>,
-    nodes: HashMap<Vertex<N>, NoInstrBasicBlock<N>>,
+    // note: ordered by the addresses, hence every traversal of the graph is reproducible
+    nodes: BTreeMap<Vertex<N>, NoInstrBasicBlock<N>>,
 }
 
 // ControlFlowGraph struct - not generic type
@@ -272,7 +461,7 @@ impl VirtualAddressGraph<u64> {
     pub fn from_cfg(cfg: &ControlFlowGraph) -> Self {
         // let mut nodes: Vec<NoInstrBasicBlock<u64>> = Vec::new();
 
-        let mut nodes: HashMap<Vertex<u64>, NoInstrBasicBlock<u64>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<u64>, NoInstrBasicBlock<u64>> = BTreeMap::new();
 
         for block in cfg.blocks() {
             let node: NoInstrBasicBlock<u64> = NoInstrBasicBlock::from_bb(block);
@@ -292,21 +481,85 @@ impl VirtualAddressGraph<u64> {
 
         vag
     }
+
+    // the same as from_cfg, but the blocks' execution counts and the edges' weights are
+    // estimated statically (see StaticProfile) - they are per FREQUENCY_SCALE calls
+    pub fn from_cfg_estimated(cfg: &ControlFlowGraph) -> Self {
+        let profile = StaticProfile::from_cfg(cfg);
+        Self::from_cfg_with_counts(cfg, profile.block_counts(), profile.edge_counts())
+    }
+
+    // the same as from_cfg, but the blocks' execution counts and the edges' weights are
+    // taken from a measured profile (e.g. see perf::parse_brstack)
+    pub fn from_cfg_profiled(cfg: &ControlFlowGraph, profile: &EdgeProfile) -> Self {
+        Self::from_cfg_with_counts(cfg, profile.block_counts(), profile.edge_counts())
+    }
+
+    // the same as from_cfg_profiled, where the profile is read from the output of
+    // `perf script -F brstack` with the given load bias (see perf::parse_brstack)
+    pub fn from_cfg_brstack<R: std::io::BufRead>(
+        cfg: &ControlFlowGraph,
+        brstack: R,
+        bias: u64,
+    ) -> Result<Self, ProfileError> {
+        let profile = perf::parse_brstack(brstack, cfg, bias)?;
+        Ok(Self::from_cfg_profiled(cfg, &profile))
+    }
+
+    // the same as from_cfg_profiled, where the profile is read from a BOLT .fdata file for
+    // the function the graph starts at (see fdata::parse_fdata)
+    pub fn from_cfg_fdata<R: std::io::BufRead>(
+        cfg: &ControlFlowGraph,
+        fdata: R,
+        function: &str,
+    ) -> Result<Self, ProfileError> {
+        let profile = fdata::parse_fdata(fdata, cfg, function)?;
+        Ok(Self::from_cfg_profiled(cfg, &profile))
+    }
+
+    // the same as from_cfg_profiled, where the profile is read from an AutoFDO text profile
+    // with the given load bias (see autofdo::parse_text)
+    pub fn from_cfg_autofdo<R: std::io::BufRead>(
+        cfg: &ControlFlowGraph,
+        text: R,
+        bias: u64,
+    ) -> Result<Self, ProfileError> {
+        let profile = autofdo::parse_text(text, cfg, bias)?;
+        Ok(Self::from_cfg_profiled(cfg, &profile))
+    }
+
+    // creates an instance from a ControlFlowGraph with the given counts of blocks and edges
+    fn from_cfg_with_counts(
+        cfg: &ControlFlowGraph,
+        blocks: HashMap<u64, usize>,
+        edges: HashMap<(u64, u64), usize>,
+    ) -> Self {
+        let counts: HashMap<Vertex<u64>, usize> = blocks
+            .into_iter()
+            .map(|(block, count)| (Vertex::Id(block), count))
+            .collect();
+        let weights: EdgeWeights<u64> = edges
+            .into_iter()
+            .map(|((from, to), count)| ((Vertex::Id(from), Vertex::Id(to)), count))
+            .collect();
+
+        VirtualAddressGraph::from_cfg(cfg).with_profile(&counts, &weights)
+    }
 }
 
 impl<N: VAGNodeId> VirtualAddressGraph<N> {
     // creates a new instance given its address and blocks
     // need: keep the fields private from scc
-    pub fn new(address: Vertex<N>, nodes: HashMap<Vertex<N>, NoInstrBasicBlock<N>>) -> Self {
+    pub fn new(address: Vertex<N>, nodes: BTreeMap<Vertex<N>, NoInstrBasicBlock<N>>) -> Self {
         VirtualAddressGraph::<N> { address, nodes }
     }
 
     // returns the list (BTreeMap - sorted by address) of (vertex, sources) pairs of an instance
-    fn sources(&self) -> BTreeMap<Vertex<N>, HashSet<Vertex<N>>> {
-        let mut sources: BTreeMap<Vertex<N>, HashSet<Vertex<N>>> = BTreeMap::new();
+    fn sources(&self) -> BTreeMap<Vertex<N>, BTreeSet<Vertex<N>>> {
+        let mut sources: BTreeMap<Vertex<N>, BTreeSet<Vertex<N>>> = BTreeMap::new();
 
         for (id, node) in self.nodes() {
-            sources.entry(*id).or_insert(HashSet::<Vertex<N>>::new());
+            sources.entry(*id).or_insert(BTreeSet::<Vertex<N>>::new());
 
             for target in node.targets() {
                 sources
@@ -315,7 +568,7 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
                         s.insert(*id);
                     })
                     .or_insert_with(|| {
-                        let mut s: HashSet<Vertex<N>> = HashSet::new();
+                        let mut s: BTreeSet<Vertex<N>> = BTreeSet::new();
                         s.insert(*id);
                         s
                     });
@@ -328,7 +581,7 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
     // MAYBE: this will be deleted later
     // an extra iteration through the nodes of the graph to update the set of sources of the vertices
     pub fn update_sources(&mut self) {
-        let sources: BTreeMap<Vertex<N>, HashSet<Vertex<N>>> = self.sources();
+        let sources: BTreeMap<Vertex<N>, BTreeSet<Vertex<N>>> = self.sources();
 
         for (id, node) in self.nodes_mut() {
             node.set_sources(sources.get(id).unwrap());
@@ -337,7 +590,7 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
 
     // MAYBE: this will be deleted later
     // returns the list (BTreeMap - sorted by address) of indegrees of an instance
-    // TODO: iterating through the elements of HashMap - is it cheap?
+    // TODO: iterating through the elements of BTreeMap - is it cheap?
     fn in_degrees(&self) -> BTreeMap<Vertex<N>, usize> {
         let mut indeg: BTreeMap<Vertex<N>, usize> = BTreeMap::new();
 
@@ -374,7 +627,7 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
     // it is really pricey in runtime, hence we would like to use it only once
     // and whenever we modify something locally, then do the update also locally there
     pub fn update_sources_and_indegrees(&mut self) {
-        let sources: BTreeMap<Vertex<N>, HashSet<Vertex<N>>> = self.sources();
+        let sources: BTreeMap<Vertex<N>, BTreeSet<Vertex<N>>> = self.sources();
 
         for (id, node) in self.nodes_mut() {
             node.set_sources(sources.get(id).unwrap());
@@ -383,18 +636,37 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
         }
     }
 
+    // sets the execution counts of the blocks and the weights of the edges (e.g. from a profile)
+    // note: the blocks and the edges missing from the graph are ignored
+    pub fn with_profile(
+        mut self,
+        counts: &HashMap<Vertex<N>, usize>,
+        weights: &EdgeWeights<N>,
+    ) -> Self {
+        for (id, node) in self.nodes_mut() {
+            node.count = counts.get(id).copied();
+            node.weights = node
+                .targets()
+                .iter()
+                .filter_map(|target| weights.get(&(*id, *target)).map(|&w| (*target, w)))
+                .collect();
+        }
+
+        self
+    }
+
     // the start virtual address
     pub fn address(&self) -> Vertex<N> {
         self.address
     }
 
     // unmutable slice of nodes
-    pub fn nodes(&self) -> &HashMap<Vertex<N>, NoInstrBasicBlock<N>> {
+    pub fn nodes(&self) -> &BTreeMap<Vertex<N>, NoInstrBasicBlock<N>> {
         &self.nodes
     }
 
     // mutable slice of nodes
-    fn nodes_mut(&mut self) -> &mut HashMap<Vertex<N>, NoInstrBasicBlock<N>> {
+    fn nodes_mut(&mut self) -> &mut BTreeMap<Vertex<N>, NoInstrBasicBlock<N>> {
         &mut self.nodes
     }
 
@@ -441,25 +713,34 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
             }
         }
 
-        let mut nodes: HashMap<Vertex<N>, NoInstrBasicBlock<N>> = HashMap::new();
+        let mut nodes: BTreeMap<Vertex<N>, NoInstrBasicBlock<N>> = BTreeMap::new();
 
         for comp in &scc {
             // the component's ID be the smallest node id in there
             let address: Vertex<N> = *comp.iter().min().unwrap();
             let mut length: usize = 0;
-            let mut targets: HashSet<Vertex<N>> = HashSet::new();
+            let mut bytes: Option<usize> = Some(0);
+            let mut targets: BTreeSet<Vertex<N>> = BTreeSet::new();
+            let mut weights: HashMap<Vertex<N>, usize> = HashMap::new();
 
             for node in comp {
                 // the block at the given address
                 let node = self.node_at_target(*node);
 
                 length += node.len();
+                bytes = bytes.zip(node.bytes()).map(|(x, y)| x + y);
 
                 for target in node.targets() {
                     if !(comp.contains(target) || targets.contains(target)) {
                         // is this .get() fast for BTreeMap ??
                         targets.insert(*(comp_dict.get(target).unwrap()));
                     }
+                    // the weights of the edges between two components add up
+                    if let (false, Some(weight)) =
+                        (comp.contains(target), node.edge_weight(*target))
+                    {
+                        *weights.entry(comp_dict[target]).or_insert(0) += weight;
+                    }
                 }
             }
 
@@ -468,7 +749,13 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
                 NoInstrBasicBlock::<N> {
                     address,
                     len: length,
-                    sources: HashSet::<Vertex<N>>::new(),
+                    bytes,
+                    branch: None,
+                    fallthrough: None,
+                    jump: None,
+                    count: None,
+                    weights,
+                    sources: BTreeSet::<Vertex<N>>::new(),
                     targets,
                     indegree: 0_usize,
                 },
@@ -490,53 +777,24 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
 
     // this method is just an inspector -> no Vertex::{Source, Sink} will be presented
     // no need to wrap the nodes in the argument into Vertex enum
+    // note: for the cost of an edge see OrderCost - runtime: O(N + E)
     pub fn cost_of_order(&self, order: &[N] /*Vec<N>*/) -> usize {
-        // the list of nodes in the given order
-        let mut ordered: Vec<&NoInstrBasicBlock<N>> = Vec::new();
-        let mut cost: usize = 0;
-
-        for address in order {
-            ordered.push(self.node_at_target(Vertex::Id(*address)));
-        }
-
-        // starting from the first node of the order we iterate through all the vertices
-        // and consider only the outgoing edges (whose are incoming edges for other, no worries)
-        // TODO: if there is a back-and-forth edge between nodes: its weight = 0, which is not correct
-        for (pos01, block) in ordered.iter().enumerate() {
-            for target in block.targets() {
-                let mut edge_weight: usize = 0;
-
-                // the given order can be any arbitrary, hence there is no binary search
-                let pos02: usize = order
-                    .iter()
-                    .position(|&x| Vertex::Id(x) == *target)
-                    .unwrap();
-
-                // note: an edge goes from the last instruction of a block to the first instruction of the other block
-                match pos01.cmp(&pos02) {
-                    // edge goes forward: pos01 and pos02 are not counted
-                    Ordering::Less => {
-                        for node in &ordered[pos01 + 1..pos02] {
-                            edge_weight += node.len();
-                        }
-                    }
-                    // edge goes backward: pos01 and pos02 are counted
-                    Ordering::Greater => {
-                        for node in &ordered[pos02..=pos01] {
-                            edge_weight += node.len();
-                        }
-                    }
-                    // loop edge: length of the given node (same as the target)
-                    Ordering::Equal => {
-                        edge_weight = block.len();
-                    }
-                }
+        OrderCost::new(self, order).cost()
+    }
 
-                cost += edge_weight;
-            }
-        }
+    // the same as cost_of_order, but the lengths of the jumps are measured in the given unit
+    pub fn cost_of_order_in(&self, order: &[N], unit: SizeUnit) -> usize {
+        OrderCost::with_unit(self, order, unit).cost()
+    }
+
+    // the cost of the given order with respect to the given objective
+    pub fn cost_of_order_with(&self, order: &[N], objective: &Objective) -> usize {
+        objective.evaluator(self, order).cost()
+    }
 
-        cost
+    // the memory footprint of the hot blocks for the given order (see CacheCost)
+    pub fn cache_of_order(&self, order: &[N], config: &CacheConfig) -> CacheCost {
+        CacheCost::of_order(self, order, &self.hot_blocks(config), config)
     }
 
     // collection of such edges that generates cycles in the component
@@ -565,12 +823,18 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
     // since the source of the incoming edges is not in the VAG - we don't have to delete anything
     pub fn add_source_vertex(&mut self, in_edges: &[(Vertex<N>, Vertex<N>)]) {
         // the sources of these edges then are merged into one vertex
-        // with 0 indegree, empty sources hashset and large length
+        // with 0 indegree, empty sources set and large length
         self.add_block_without_update(NoInstrBasicBlock {
             // the reason why we masked N into Vertrex<N> is to have the Vertex::{Source, Sink} fields
             address: Vertex::Source,
             len: 99999,
-            sources: HashSet::<Vertex<N>>::new(),
+            bytes: None,
+            branch: None,
+            fallthrough: None,
+            jump: None,
+            count: None,
+            weights: HashMap::new(),
+            sources: BTreeSet::<Vertex<N>>::new(),
             targets: in_edges.iter().map(|(_, t)| *t).collect(),
             indegree: 0,
         });
@@ -597,13 +861,19 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
     // note: used only in scc::to_acyclic_vag hence the indegree update is there
     pub fn add_sink_vertex(&mut self, out_edges: &[(Vertex<N>, Vertex<N>)]) {
         // the targets of these edges then are merged into one vertex
-        // with given indegree, hashset of sources and small length
+        // with given indegree, set of sources and small length
         self.add_block_without_update(NoInstrBasicBlock {
             // the reason why we masked N into Vertrex<N> is to have the Vertex::{Source, Sink} fields
             address: Vertex::Sink,
             len: 0,
+            bytes: Some(0),
+            branch: None,
+            fallthrough: None,
+            jump: None,
+            count: None,
+            weights: HashMap::new(),
             sources: out_edges.iter().map(|(s, _)| *s).collect(),
-            targets: HashSet::<Vertex<N>>::new(),
+            targets: BTreeSet::<Vertex<N>>::new(),
             indegree: out_edges.len(),
         });
 
@@ -621,32 +891,41 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
     // gets a VAG and returns the "optimal" order of its vertices
     // the final order won't contain Vertex::{Source, Sink}, hence we can unwrap the nodeids
     pub fn weighted_order(&self) -> Vec<N> {
+        self.weighted_order_by(&DegreeLength)
+    }
+
+    // the same as weighted_order, but the ties of Kahn's algorithm are broken by the given
+    // strategy (see TieBreak)
+    pub fn weighted_order_by<T: TieBreak<N> + ?Sized>(&self, tie_break: &T) -> Vec<N> {
         // TODO: is_cyclic_directed is recursive - maybe use topsort, but that seems redundant
         if !(is_cyclic_directed(self)) {
             // Kahn's algorithm
             let mut kahngraph: KahnGraph<N> = KahnGraph::from_vag(self);
             // if there is no directed cycle in the graph, then we only have Vertex::Id variants
             kahngraph
-                .kahn_algorithm()
+                .kahn_algorithm(tie_break)
                 .iter()
                 .map(|x| x.id().unwrap())
                 .collect()
         } else {
-            // collapse the strongly connected components into single vertices
-            let condensed = self.condense();
-
-            // Kahn's algorithm for the condensed graph
-            let mut kahngraph: KahnGraph<N> = KahnGraph::from_vag(&condensed);
-            let mut topsort_condensed = kahngraph.kahn_algorithm();
-
             // Kahn's algorithm for the strongly connected components
             let components: Vec<Component<N>> = Component::from_vag(self);
 
+            // the component of every block
+            let comp_dict: HashMap<Vertex<N>, Vertex<N>> = components
+                .iter()
+                .flat_map(|comp| comp.nodes().iter().map(|&node| (node, comp.compid())))
+                .collect();
+            // the last placed block of every component - as a block of the condensed graph
+            let mut exits: HashMap<Vertex<N>, NoInstrBasicBlock<N>> = HashMap::new();
+
             // the order inside the components collected in a HashMap - key: id, value: ordered vector
             let mut ordered_components: HashMap<Vertex<N>, Vec<Vertex<N>>> = HashMap::new();
             // let mut ordered_components: Vec<Vec<Vertex<N>>> = Vec::new();
 
             for comp in components {
+                let mut exit: Vertex<N> = comp.compid();
+
                 // if the component is trivial (i.e. single vertex) -> do nothing
                 if !comp.trivial() {
                     // MAYBE: this is very expensive in runtime -> modify the component struct ?
@@ -656,15 +935,30 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
 
                     // Kahn's algorithm for the given component
                     let mut kahngraph: KahnGraph<N> = KahnGraph::from_vag(&comp_vag);
-                    let mut ord_comp: Vec<Vertex<N>> = kahngraph.kahn_algorithm();
+                    let mut ord_comp: Vec<Vertex<N>> = kahngraph.kahn_algorithm(tie_break);
 
                     // delete the auxiliary nodes (Vertex::Source and Vertex::Sink) from the order
                     ord_comp.retain(|&x| x != Vertex::Source && x != Vertex::Sink);
 
+                    exit = *ord_comp.last().unwrap();
                     ordered_components.insert(comp.compid(), ord_comp);
                 }
+
+                exits.insert(comp.compid(), self.condensed_exit(exit, &comp_dict));
             }
 
+            // collapse the strongly connected components into single vertices
+            let condensed = self.condense();
+
+            // Kahn's algorithm for the condensed graph
+            // note: the tie-break sees the last block placed from the previous component, since
+            //       a condensed component has no fall-through (and its edges are merged)
+            let mut kahngraph: KahnGraph<N> = KahnGraph::from_vag(&condensed);
+            let mut topsort_condensed = kahngraph.kahn_algorithm(&ComponentExit {
+                tie_break,
+                exits: &exits,
+            });
+
             // insert the inside orders of the components in the ordered components list
             // note: the Vertex enum wrap is not needed anymore
             let mut topsort: Vec<N> = Vec::new();
@@ -686,6 +980,183 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
         }
     }
 
+    // the given block as a block of the condensed graph (see condense): its targets, the weights
+    // of its edges and its fall-through are replaced by the components they are in
+    fn condensed_exit(
+        &self,
+        block: Vertex<N>,
+        comp_dict: &HashMap<Vertex<N>, Vertex<N>>,
+    ) -> NoInstrBasicBlock<N> {
+        let node = self.node_at_target(block);
+
+        let mut weights: HashMap<Vertex<N>, usize> = HashMap::new();
+        for (target, weight) in &node.weights {
+            *weights.entry(comp_dict[target]).or_insert(0) += weight;
+        }
+
+        NoInstrBasicBlock::<N> {
+            address: node.address,
+            len: node.len,
+            bytes: node.bytes,
+            branch: node.branch,
+            fallthrough: node.fallthrough.map(|x| comp_dict[&x]),
+            jump: node.jump.map(|x| comp_dict[&x]),
+            count: node.count,
+            weights,
+            sources: BTreeSet::<Vertex<N>>::new(),
+            targets: node.targets.iter().map(|x| comp_dict[x]).collect(),
+            indegree: node.indegree,
+        }
+    }
+
+    // static estimate of the edge weights (i.e. how often an edge is taken) when no profile is given
+    // edges staying inside a loop (a non-trivial strongly connected component) are considered
+    // to be taken LOOP_WEIGHT times more often than the others
+    pub fn static_edge_weights(&self) -> EdgeWeights<N> {
+        // the component's index (for the non-trivial components only)
+        let mut comp_dict: HashMap<Vertex<N>, usize> = HashMap::new();
+        for (i, comp) in tarjan_scc(self).iter().enumerate() {
+            if comp.len() > 1 {
+                for node in comp {
+                    comp_dict.insert(*node, i);
+                }
+            }
+        }
+
+        let mut weights: EdgeWeights<N> = HashMap::new();
+        for (id, node) in self.nodes() {
+            for target in node.targets() {
+                let in_loop = id == target
+                    || comp_dict
+                        .get(id)
+                        .is_some_and(|comp| comp_dict.get(target) == Some(comp));
+                weights.insert((*id, *target), if in_loop { LOOP_WEIGHT } else { 1 });
+            }
+        }
+
+        weights
+    }
+
+    // whether the weights of the edges are known (e.g. from a profile) for some blocks
+    pub fn has_profile(&self) -> bool {
+        self.nodes().values().any(|node| node.has_edge_weights())
+    }
+
+    // the weights of the edges: the profiled counts if there is a profile (the edges missing
+    // from it are never taken), otherwise the static estimate
+    pub fn edge_weights(&self) -> EdgeWeights<N> {
+        if !self.has_profile() {
+            return self.static_edge_weights();
+        }
+
+        let mut weights: EdgeWeights<N> = HashMap::new();
+        for (id, node) in self.nodes() {
+            for target in node.targets() {
+                weights.insert((*id, *target), node.edge_weight(*target).unwrap_or(0));
+            }
+        }
+
+        weights
+    }
+
+    // how many times the edges are taken in the cost of an order: the profiled counts if
+    // there is a profile, otherwise every edge is taken once
+    pub fn edge_frequencies(&self) -> EdgeWeights<N> {
+        match self.has_profile() {
+            true => self.edge_weights(),
+            false => self
+                .nodes()
+                .iter()
+                .flat_map(|(id, node)| node.targets().iter().map(|target| ((*id, *target), 1)))
+                .collect(),
+        }
+    }
+
+    // the blocks on the hot path: the endpoints of the hot edges, i.e. those at least as heavy
+    // as the given share of the heaviest one (see edge_weights and CacheConfig::hot_percent)
+    // note: if there are no loops, then every edge is equally hot - and if there are no edges
+    //       at all, then the entry is the hot path
+    pub fn hot_blocks(&self, config: &CacheConfig) -> BTreeSet<Vertex<N>> {
+        let weights = self.edge_weights();
+        let heaviest = weights.values().copied().max().unwrap_or(0);
+
+        let mut hot: BTreeSet<Vertex<N>> = weights
+            .iter()
+            .filter(|(_, &weight)| config.is_hot(weight, heaviest))
+            .flat_map(|(&(from, to), _)| [from, to])
+            .collect();
+        if hot.is_empty() {
+            hot.insert(self.address());
+        }
+
+        hot
+    }
+
+    // gets a VAG and returns an order of its vertices given by Pettis and Hansen's greedy
+    // chain formation, where the edges' weights come from the profile if there is one,
+    // otherwise they are statically estimated (see edge_weights)
+    pub fn pettis_hansen_order(&self) -> Vec<N> {
+        let weights = self.edge_weights();
+        let mut chaingraph: ChainGraph<N> = ChainGraph::from_vag(self, &weights);
+
+        chaingraph
+            .pettis_hansen()
+            .iter()
+            .map(|x| x.id().unwrap())
+            .collect()
+    }
+
+    // gets a VAG and returns an order of its vertices with minimal cost_of_order, among those
+    // that start with the entry block
+    // note: runtime and memory are exponential in the number of nodes, hence the graph can
+    //       not have more than OPTIMAL_MAX_NODES nodes
+    pub fn optimal_order(&self) -> Vec<N> {
+        let exactgraph: ExactGraph<N> = ExactGraph::from_vag(self);
+        let (_, order) = exactgraph.optimal();
+
+        order.iter().map(|x| x.id().unwrap()).collect()
+    }
+
+    // improves the given order by local search with at most budget many cost evaluations,
+    // decreasing the given objective
+    // note:    the first node of the given order is kept in place
+    // note:    the order must contain every node exactly once (otherwise it panics), hence
+    //          it is only called by bbsort::improve_order_with, which checks the order
+    pub(crate) fn improve_order_with(
+        &self,
+        order: &[N],
+        budget: usize,
+        objective: &Objective,
+    ) -> Vec<N> {
+        LocalSearch::new(self, budget, *objective).improve(order)
+    }
+
+    // searches for a better order than the given one by simulated annealing, decreasing the
+    // given objective
+    // note:    the first node of the given order is kept in place
+    // note:    the order must contain every node exactly once (otherwise it panics), hence
+    //          it is only called by bbsort::anneal_order_with, which checks the order
+    pub(crate) fn anneal_order_with(
+        &self,
+        order: &[N],
+        config: &AnnealConfig,
+        objective: &Objective,
+    ) -> Vec<N> {
+        Annealing::new(self, config, *objective).anneal(order)
+    }
+
+    // splits the given order into the hot blocks and the ones executed at most threshold
+    // times, keeping their relative order (see HotColdSplit)
+    pub fn split_order(&self, order: &[N], threshold: usize) -> HotColdSplit<N> {
+        HotColdSplit::of_order(self, order, threshold)
+    }
+
+    // the size of the code and the number of taken branches once the branches are re-encoded
+    // for the given order (see EncodingCost) - to be reported alongside cost_of_order
+    pub fn encoding_of_order(&self, order: &[N]) -> EncodingCost {
+        EncodingCost::of_order(self, order)
+    }
+
     // from graph to .dot
     pub fn render_to<W: std::io::Write>(&self, output: &mut W) -> dot2::Result {
         dot2::render(self, output)
@@ -714,7 +1185,7 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
     //          hence this method is only used when we generate a VAGraph from a given graph-like input
     //          (even if the presence of such edges does not matter for our algorithm - which can not see them at all)
     pub fn erase_outgoing_edges(&mut self) -> HashSet<(N, N)> {
-        let nodes: HashSet<Vertex<N>> = self.nodes().keys().copied().collect();
+        let nodes: BTreeSet<Vertex<N>> = self.nodes().keys().copied().collect();
 
         let mut outgoing_edges: HashSet<(N, N)> = HashSet::new();
 
@@ -734,9 +1205,9 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
 
     // running a DFS it checks whether if all the nodes are reachable from the entry or not in the VAGraph
     // TODO: make it a bit more sophisticated
-    pub fn unreachable_from_start(&self) -> HashSet<Vertex<N>> {
-        // the reachable nodes will be collected in reachable hashset
-        let mut reachable: HashSet<Vertex<N>> = HashSet::new();
+    pub fn unreachable_from_start(&self) -> BTreeSet<Vertex<N>> {
+        // the reachable nodes will be collected in reachable set
+        let mut reachable: BTreeSet<Vertex<N>> = BTreeSet::new();
 
         depth_first_search(&self, Some(self.address()), |event| {
             if let DfsEvent::Discover(block, _) = event {
@@ -745,11 +1216,11 @@ impl<N: VAGNodeId> VirtualAddressGraph<N> {
         });
 
         // the unreachable nodes are obtained using difference
-        let unreachable: HashSet<Vertex<N>> = self
+        let unreachable: BTreeSet<Vertex<N>> = self
             .nodes()
             .keys()
             .copied()
-            .collect::<HashSet<Vertex<N>>>()
+            .collect::<BTreeSet<Vertex<N>>>()
             .difference(&reachable)
             .copied()
             .collect();
@@ -936,6 +1407,29 @@ impl<'a, N: VAGNodeId> dot2::GraphWalk<'a> for VirtualAddressGraph<N> {
     }
 }
 
+// the tie-break of the condensed graph: the last placed (condensed) component is replaced by
+// the last block placed from it (see VirtualAddressGraph::condensed_exit)
+struct ComponentExit<'a, N: VAGNodeId, T: TieBreak<N> + ?Sized> {
+    tie_break: &'a T,
+    exits: &'a HashMap<Vertex<N>, NoInstrBasicBlock<N>>,
+}
+
+impl<N: VAGNodeId, T: TieBreak<N> + ?Sized> TieBreak<N> for ComponentExit<'_, N, T> {
+    fn compare(
+        &self,
+        last: Option<&NoInstrBasicBlock<N>>,
+        a: &NoInstrBasicBlock<N>,
+        b: &NoInstrBasicBlock<N>,
+    ) -> Ordering {
+        let last = last.map(|x| &self.exits[&x.address()]);
+        self.tie_break.compare(last, a, b)
+    }
+
+    fn uses_last(&self) -> bool {
+        self.tie_break.uses_last()
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////////
 
 // trait: NodeWeight
@@ -947,6 +1441,30 @@ impl<N: VAGNodeId> NodeWeight for &VirtualAddressGraph<N> {
     fn weight(&self, node: Self::Node) -> usize {
         self.node_at_target(node).len()
     }
+
+    fn byte_size(&self, node: Self::Node) -> Option<usize> {
+        self.node_at_target(node).bytes()
+    }
+
+    fn branch_size(&self, node: Self::Node) -> Option<usize> {
+        self.node_at_target(node).branch()
+    }
+
+    fn fallthrough(&self, node: Self::Node) -> Option<Self::Node> {
+        self.node_at_target(node).fallthrough()
+    }
+
+    fn jump(&self, node: Self::Node) -> Option<Self::Node> {
+        self.node_at_target(node).jump()
+    }
+
+    fn edge_weight(&self, from: Self::Node, to: Self::Node) -> Option<usize> {
+        self.node_at_target(from).edge_weight(to)
+    }
+
+    fn execution_count(&self, node: Self::Node) -> Option<usize> {
+        self.node_at_target(node).count()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////
@@ -957,6 +1475,17 @@ impl<N: VAGNodeId> NodeWeight for &VirtualAddressGraph<N> {
 pub struct UnwrappedBasicBlock<N: VAGNodeId> {
     address: N,
     len: usize,
+    #[serde(default)]
+    bytes: Option<usize>,
+    #[serde(default)]
+    branch: Option<usize>,
+    fallthrough: Option<N>,
+    jump: Option<N>,
+    #[serde(default)]
+    count: Option<usize>,
+    // the number of times the edges to the given targets are taken
+    #[serde(default = "HashMap::new")]
+    weights: HashMap<N, usize>,
     targets: Vec<N>,
     indegree: usize,
 }
@@ -970,7 +1499,17 @@ impl<N: VAGNodeId> UnwrappedBasicBlock<N> {
         NoInstrBasicBlock {
             address: Vertex::Id(self.address),
             len: self.len,
-            sources: HashSet::<Vertex<N>>::new(),
+            bytes: self.bytes,
+            branch: self.branch,
+            fallthrough: self.fallthrough.map(Vertex::Id),
+            jump: self.jump.map(Vertex::Id),
+            count: self.count,
+            weights: self
+                .weights
+                .iter()
+                .map(|(&target, &weight)| (Vertex::Id(target), weight))
+                .collect(),
+            sources: BTreeSet::<Vertex<N>>::new(),
             targets: self.targets.iter().map(|&x| Vertex::Id(x)).collect(),
             indegree: self.indegree,
         }
@@ -989,7 +1528,7 @@ impl<N: VAGNodeId> UnwrappedVAGraph<N> {
     }
 
     pub fn to_vag(&self) -> VirtualAddressGraph<N> {
-        let mut wrappednodes: HashMap<Vertex<N>, NoInstrBasicBlock<N>> = HashMap::new();
+        let mut wrappednodes: BTreeMap<Vertex<N>, NoInstrBasicBlock<N>> = BTreeMap::new();
 
         for block in self.nodes() {
             wrappednodes.insert(Vertex::Id(block.address()), block.to_nibb());
@@ -1006,3 +1545,29 @@ impl<N: VAGNodeId> UnwrappedVAGraph<N> {
         vag
     }
 }
+
+// the VAGraph of the given (address, length, targets) blocks entered at entry, the test
+// fixture of the sorting and cost modules
+#[cfg(test)]
+pub(crate) fn vag_from_blocks(
+    entry: u64,
+    blocks: &[(u64, usize, &[u64])],
+) -> VirtualAddressGraph<u64> {
+    let nodes = blocks
+        .iter()
+        .map(|&(address, len, targets)| {
+            let node = NoInstrBasicBlock::new(
+                Vertex::Id(address),
+                len,
+                BTreeSet::new(),
+                targets.iter().map(|&t| Vertex::Id(t)).collect(),
+                0,
+            );
+            (Vertex::Id(address), node)
+        })
+        .collect();
+
+    let mut vag = VirtualAddressGraph::new(Vertex::Id(entry), nodes);
+    vag.update_sources_and_indegrees();
+    vag
+}
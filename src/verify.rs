@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use iced_x86::FlowControl;
+
+use crate::binary::Binary;
+use crate::cfg::*;
+use crate::rewrite::*;
+
+// the padding after the rewritten code
+const INT3: u8 = 0xcc;
+
+/// A block whose successors in the rewritten code differ from the ones in the original
+/// control flow graph.
+///
+/// # Fields
+///
+/// * `block`       - the (old) address of the block;
+/// * `expected`    - the (old) addresses of the successors in the original graph;
+/// * `found`       - the successors in the rewritten code: the old addresses of the blocks, or the new address if it is not the image of a block;
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdgeMismatch {
+    pub block: u64,
+    pub expected: Vec<u64>,
+    pub found: Vec<u64>,
+}
+
+// the control flow graph of the rewritten code with the address map of the rewrite
+struct Rewritten {
+    cfg: ControlFlowGraph,
+    // the old address of every block by its new address
+    images: BTreeMap<u64, u64>,
+}
+
+impl Rewritten {
+    // the block of the rewritten graph containing the given address
+    fn containing(&self, address: u64) -> Option<&BasicBlock> {
+        self.cfg
+            .blocks()
+            .iter()
+            .find(|b| b.address() <= address && address <= b.end_address())
+    }
+
+    // where the execution continues from the image of the block at the given new address:
+    // either it runs into the image of another block, or it leaves the block of the
+    // rewritten graph containing it
+    fn successors(&self, start: u64) -> Option<BTreeSet<u64>> {
+        let block = self.containing(start)?;
+        let next = block
+            .instructions()
+            .iter()
+            .map(|x| x.ip())
+            .find(|&ip| ip > start && self.images.contains_key(&ip));
+        if let Some(next) = next {
+            return Some(BTreeSet::from([self.images[&next]]));
+        }
+
+        let mut visited: HashSet<u64> = HashSet::new();
+        Some(
+            block
+                .successors()
+                .into_iter()
+                .flat_map(|x| self.resolve(x, &mut visited))
+                .collect(),
+        )
+    }
+
+    // the old address of the block at the given new address, where the inserted jumps (a
+    // lone jump which is not the image of a block) are followed
+    // note: anything else is reported by its new address
+    fn resolve(&self, address: u64, visited: &mut HashSet<u64>) -> Vec<u64> {
+        if let Some(&old) = self.images.get(&address) {
+            return vec![old];
+        }
+
+        let inserted = self.cfg.blocks().iter().find(|b| {
+            b.address() == address
+                && b.instructions().len() == 1
+                && b.instructions()[0].flow_control() == FlowControl::UnconditionalBranch
+        });
+        match inserted {
+            Some(jump) if visited.insert(address) => jump
+                .successors()
+                .into_iter()
+                .flat_map(|x| self.resolve(x, visited))
+                .collect(),
+            _ => vec![address],
+        }
+    }
+}
+
+/// Checks whether the rewritten code of a function has the same control flow as the
+/// original one: the rewritten code is disassembled again, and for every block of the
+/// original graph the successors of its image are compared to its successors (under the
+/// address map of the rewrite). Note that the jumps inserted by the rewrite are followed,
+/// and a block may run into the next one if the jump between them was removed.
+///
+/// # Arguments
+///
+/// * `cfg`         - the control flow graph of the original function;
+/// * `relocated`   - the rewritten code of the function (see relocate);
+///
+/// The blocks whose successors differ are returned, hence the rewrite is correct if there
+/// are none.
+///
+pub fn verify_relocation(cfg: &ControlFlowGraph, relocated: &RelocatedCode) -> Vec<EdgeMismatch> {
+    // the code is followed by an int3: the graph also explores the address after a jump
+    let mut code = relocated.code.clone();
+    code.push(INT3);
+    let binary = Binary::from_code(relocated.address, code);
+    let entry = relocated
+        .blocks
+        .get(&cfg.address())
+        .copied()
+        .unwrap_or(relocated.address);
+    let rewritten = Rewritten {
+        cfg: ControlFlowGraph::from_address(&binary, entry),
+        images: relocated
+            .blocks
+            .iter()
+            .map(|(&old, &new)| (new, old))
+            .collect(),
+    };
+
+    let mut mismatches: Vec<EdgeMismatch> = Vec::new();
+    for block in cfg.blocks() {
+        let expected: BTreeSet<u64> = block.successors().into_iter().collect();
+        let found: BTreeSet<u64> = relocated
+            .blocks
+            .get(&block.address())
+            .and_then(|&new| rewritten.successors(new))
+            .unwrap_or_default();
+
+        if expected != found {
+            mismatches.push(EdgeMismatch {
+                block: block.address(),
+                expected: expected.into_iter().collect(),
+                found: found.into_iter().collect(),
+            });
+        }
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relocations_are_equivalent() {
+        let cfg = counting_loop_cfg();
+        for order in [
+            [0x1000, 0x1002, 0x1009, 0x100e, 0x100f],
+            [0x1000, 0x1002, 0x1009, 0x100f, 0x100e],
+            [0x1000, 0x1009, 0x100e, 0x100f, 0x1002],
+            [0x1002, 0x100f, 0x1000, 0x100e, 0x1009],
+        ] {
+            let relocated = relocate(&cfg, &order, 0x2000).unwrap();
+            assert_eq!(verify_relocation(&cfg, &relocated), [], "order: {order:x?}");
+        }
+    }
+
+    #[test]
+    fn broken_relocation() {
+        let cfg = counting_loop_cfg();
+        let mut relocated =
+            relocate(&cfg, &[0x1000, 0x1002, 0x1009, 0x100e, 0x100f], 0x2000).unwrap();
+
+        // jl 0x2002 -> jl 0x2004, which is in the middle of the loop
+        assert_eq!(relocated.code[0x8], 0xf9);
+        relocated.code[0x8] = 0xfb;
+
+        assert_eq!(
+            verify_relocation(&cfg, &relocated),
+            [EdgeMismatch {
+                block: 0x1002,
+                expected: vec![0x1002, 0x1009],
+                found: vec![0x2004],
+            }]
+        );
+    }
+}